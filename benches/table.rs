@@ -0,0 +1,79 @@
+use std::{collections::HashMap, hint::black_box, io, rc::Rc, time::Instant};
+
+use crafting_interpreters_rust::vm::{
+    memory::Heap, table::Table, value::Value, InterpretResult, VM,
+};
+
+const GLOBALS: usize = 64;
+const ROUNDS: usize = 20_000;
+
+// Scripts that spend their time reading and reassigning globals.
+const SCRIPTS: [(&str, &str); 2] = [
+    ("fib.lox", include_str!("lox/fib.lox")),
+    ("counter.lox", include_str!("lox/counter.lox")),
+];
+
+fn main() {
+    for (name, source) in SCRIPTS {
+        measure_script(name, source);
+    }
+
+    // The same access pattern on the table alone, against a HashMap keyed by
+    // the names. The VM interns each name once when compiling the script, so
+    // the Table side pays for interning the names on every run.
+    let names: Vec<String> = (0..GLOBALS).map(|i| format!("global{i}")).collect();
+
+    let mut heap = Heap::new();
+    let table = measure("vm::table::Table", || {
+        let keys: Vec<_> = names.iter().map(|name| heap.copy_string(name)).collect();
+        let mut globals = Table::new();
+        for key in keys.iter() {
+            globals.set(*key, Value::from(0.0));
+        }
+        for round in 0..ROUNDS {
+            for key in keys.iter() {
+                let value = globals.get(*key).and_then(|value| value.as_number());
+                globals.set(*key, Value::from(value.unwrap_or_default() + round as f64));
+            }
+        }
+        black_box(globals.get(keys[0]));
+    });
+
+    let hash_map = measure("std::collections::HashMap", || {
+        let keys: Vec<Rc<str>> = names.iter().map(|name| Rc::from(name.as_str())).collect();
+        let mut globals = HashMap::new();
+        for key in keys.iter() {
+            globals.insert(Rc::clone(key), Value::from(0.0));
+        }
+        for round in 0..ROUNDS {
+            for key in keys.iter() {
+                let value = globals.get(key).and_then(|value| value.as_number());
+                globals.insert(
+                    Rc::clone(key),
+                    Value::from(value.unwrap_or_default() + round as f64),
+                );
+            }
+        }
+        black_box(globals.get(&keys[0]));
+    });
+
+    println!("speedup: {:.2}x", hash_map / table);
+}
+
+fn measure_script(name: &str, source: &str) {
+    let mut vm = VM::new_with_output(io::sink());
+    assert_eq!(vm.interpret(source), InterpretResult::Ok);
+    let start = Instant::now();
+    vm.interpret(source);
+    let millis = start.elapsed().as_secs_f64() * 1000.0;
+    println!("{name:<28} {millis:>8.2} ms/run");
+}
+
+fn measure<F: FnMut()>(name: &str, mut workload: F) -> f64 {
+    workload();
+    let start = Instant::now();
+    workload();
+    let nanos = start.elapsed().as_nanos() as f64 / (GLOBALS * ROUNDS) as f64;
+    println!("{name:<28} {nanos:>8.2} ns/access");
+    nanos
+}
@@ -1,12 +1,143 @@
-use crafting_interpreters_rust::vm::chunk::{Chunk, OpCode};
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    process::ExitCode,
+};
 
-fn main() {
-    let mut chunk = Chunk::new();
+use anyhow::Result;
+use crafting_interpreters_rust::{
+    vm::{InterpretResult, VM},
+    walk_tree::exit_code,
+};
+use rustyline::{error::ReadlineError, Editor};
 
-    let constant = chunk.add_constant(1.2);
-    chunk.write(OpCode::Constant, 123);
-    chunk.write(constant as u8, 123);
+const USAGE: &str = "\
+Usage: clox [options] [path]
+       clox [options] --compile <path> -o <output>
 
-    chunk.write(OpCode::Return, 123);
-    chunk.disassemble("test chunk");
+Options:
+    --disassemble       Print the bytecode before running the script
+    --disassemble-only  Print the bytecode instead of running the script
+    --trace             Print the stack and each instruction to stderr as it runs";
+
+#[derive(Default)]
+struct Options {
+    path: Option<String>,
+    output: Option<String>,
+    compile: bool,
+    disassemble: bool,
+    trace: bool,
+    run: bool,
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut options = Options {
+            run: true,
+            ..Options::default()
+        };
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--compile" => options.compile = true,
+                "-o" => options.output = Some(args.next()?),
+                "--disassemble" => options.disassemble = true,
+                "--trace" => options.trace = true,
+                "--disassemble-only" => {
+                    options.disassemble = true;
+                    options.run = false;
+                }
+                _ if arg.starts_with('-') || options.path.is_some() => return None,
+                _ => options.path = Some(arg),
+            }
+        }
+        let valid = if options.compile {
+            options.path.is_some() && options.output.is_some()
+        } else {
+            options.output.is_none() && (options.path.is_some() || !options.disassemble)
+        };
+        valid.then_some(options)
+    }
+}
+
+fn main() -> Result<ExitCode> {
+    let Some(options) = Options::parse(env::args()) else {
+        eprintln!("{USAGE}");
+        return Ok(exit_code::usage());
+    };
+    let mut vm = VM::new_with_output(io::stdout());
+    if options.trace {
+        vm.set_trace(Some(Box::new(io::stderr())));
+    }
+    match &options.path {
+        Some(path) => run_file(&mut vm, path, &options),
+        None => run_prompt(&mut vm),
+    }
+}
+
+fn run_file<W: io::Write>(vm: &mut VM<W>, path: &str, options: &Options) -> Result<ExitCode> {
+    let chunk = if path.ends_with(".loxc") {
+        let mut file = BufReader::new(File::open(path)?);
+        match vm.load(&mut file) {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                eprintln!("Can't load '{path}': {err}");
+                return Ok(exit_code::data_err());
+            }
+        }
+    } else {
+        match vm.compile(&fs::read_to_string(path)?) {
+            Some(chunk) => chunk,
+            None => return Ok(exit_code::data_err()),
+        }
+    };
+
+    if options.disassemble {
+        chunk.disassemble(path, &mut io::stdout().lock())?;
+    }
+    if let Some(output_path) = options.output.as_deref().filter(|_| options.compile) {
+        let mut output = BufWriter::new(File::create(output_path)?);
+        chunk.write_to(&mut output)?;
+        output.flush()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if !options.run {
+        return Ok(ExitCode::SUCCESS);
+    }
+    Ok(exit_code_of(vm.execute()))
+}
+
+fn exit_code_of(result: InterpretResult) -> ExitCode {
+    match result {
+        InterpretResult::Ok => ExitCode::SUCCESS,
+        InterpretResult::CompileError => exit_code::data_err(),
+        InterpretResult::RuntimeError => exit_code::software(),
+    }
+}
+
+fn run_prompt<W: io::Write>(vm: &mut VM<W>) -> Result<ExitCode> {
+    let mut editor = Editor::<()>::new()?;
+    loop {
+        match editor.readline("> ") {
+            Ok(line) => {
+                editor.add_history_entry(line.as_str());
+                vm.interpret(&line);
+            }
+            Err(ReadlineError::Interrupted) => {
+                println!("CTRL-C");
+                break;
+            }
+            Err(ReadlineError::Eof) => {
+                println!("CTRL-D");
+                break;
+            }
+            Err(err) => {
+                eprintln!("Read line error: {err:?}");
+                break;
+            }
+        }
+    }
+    editor.save_history("lox_history.txt")?;
+    Ok(ExitCode::SUCCESS)
 }
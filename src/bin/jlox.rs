@@ -1,18 +1,65 @@
-use std::{env, io, process::ExitCode};
+use std::{
+    env,
+    io::{self, IsTerminal},
+    process::ExitCode,
+};
 
 use anyhow::Result;
-use crafting_interpreters_rust::walk_tree::{error::ErrorReporter, exit_code, lox::Lox};
+use crafting_interpreters_rust::walk_tree::{
+    error::{ErrorFormat, ErrorReporter},
+    exit_code,
+    lox::Lox,
+};
+
+const USAGE: &str = "\
+Usage: jlox [options] [script]
+
+Options:
+    --error-format=<short|human|json>  Print errors one per line, with the source
+                                       line underlined, or as JSON objects
+                                       (default: short)
+    --color=<auto|always|never>        Color errors printed in the human format";
+
+struct Options {
+    path: Option<String>,
+    format: ErrorFormat,
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut path = None;
+        let mut format = ErrorFormat::Short;
+        let mut color = io::stderr().is_terminal();
+        for arg in args.skip(1) {
+            match arg.as_str() {
+                "--error-format=short" => format = ErrorFormat::Short,
+                "--error-format=human" => format = ErrorFormat::Human { color },
+                "--error-format=json" => format = ErrorFormat::Json,
+                "--color=auto" => color = io::stderr().is_terminal(),
+                "--color=always" => color = true,
+                "--color=never" => color = false,
+                _ if arg.starts_with('-') || path.is_some() => return None,
+                _ => path = Some(arg),
+            }
+        }
+        // `--color` may come after `--error-format`.
+        if let ErrorFormat::Human { color: human_color } = &mut format {
+            *human_color = color;
+        }
+        Some(Self { path, format })
+    }
+}
 
 fn main() -> Result<ExitCode> {
-    let args: Vec<_> = env::args().collect();
-    if args.len() > 2 {
-        eprintln!("Usage: walk_tree [script]");
+    let Some(options) = Options::parse(env::args()) else {
+        eprintln!("{USAGE}");
         return Ok(exit_code::usage());
-    }
+    };
     let error_reporter = ErrorReporter::new();
+    error_reporter.set_format(options.format);
     let mut lox = Lox::new(&error_reporter, io::stdout());
-    if args.len() == 2 {
-        lox.run_file(&args[1])
+    if let Some(path) = options.path {
+        lox.run_file(&path)
     } else {
         lox.run_prompt()
     }
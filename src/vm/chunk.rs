@@ -3,11 +3,130 @@ use super::{
     value::{Value, ValueArray},
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum OpCode {
     Constant,
+    ConstantLong,
+    Nil,
+    True,
+    False,
+    Equal,
+    Greater,
+    Less,
+    Pop,
+    GetLocal,
+    SetLocal,
+    DefineGlobal,
+    DefineGlobalLong,
+    GetGlobal,
+    GetGlobalLong,
+    SetGlobal,
+    SetGlobalLong,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    GetSuper,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Invoke,
+    SuperInvoke,
+    Closure,
+    CloseUpvalue,
     Return,
+    Class,
+    Inherit,
+    Method,
+    GetPropertyLong,
+    SetPropertyLong,
+    GetSuperLong,
+    InvokeLong,
+    SuperInvokeLong,
+    ClosureLong,
+    ClassLong,
+    MethodLong,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 48] = [
+        OpCode::Constant,
+        OpCode::ConstantLong,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Equal,
+        OpCode::Greater,
+        OpCode::Less,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::DefineGlobal,
+        OpCode::DefineGlobalLong,
+        OpCode::GetGlobal,
+        OpCode::GetGlobalLong,
+        OpCode::SetGlobal,
+        OpCode::SetGlobalLong,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Invoke,
+        OpCode::SuperInvoke,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+        OpCode::GetPropertyLong,
+        OpCode::SetPropertyLong,
+        OpCode::GetSuperLong,
+        OpCode::InvokeLong,
+        OpCode::SuperInvokeLong,
+        OpCode::ClosureLong,
+        OpCode::ClassLong,
+        OpCode::MethodLong,
+    ];
+
+    /// The form of an instruction that takes a 24-bit constant index instead
+    /// of a single byte.
+    pub fn long(self) -> Option<OpCode> {
+        match self {
+            OpCode::Constant => Some(OpCode::ConstantLong),
+            OpCode::DefineGlobal => Some(OpCode::DefineGlobalLong),
+            OpCode::GetGlobal => Some(OpCode::GetGlobalLong),
+            OpCode::SetGlobal => Some(OpCode::SetGlobalLong),
+            OpCode::GetProperty => Some(OpCode::GetPropertyLong),
+            OpCode::SetProperty => Some(OpCode::SetPropertyLong),
+            OpCode::GetSuper => Some(OpCode::GetSuperLong),
+            OpCode::Invoke => Some(OpCode::InvokeLong),
+            OpCode::SuperInvoke => Some(OpCode::SuperInvokeLong),
+            OpCode::Closure => Some(OpCode::ClosureLong),
+            OpCode::Class => Some(OpCode::ClassLong),
+            OpCode::Method => Some(OpCode::MethodLong),
+            _ => None,
+        }
+    }
 }
 
 impl From<OpCode> for u8 {
@@ -16,9 +135,20 @@ impl From<OpCode> for u8 {
     }
 }
 
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL.get(usize::from(value)).copied().ok_or(value)
+    }
+}
+
+/// The largest constant index a long instruction can address.
+pub const MAX_LONG_CONSTANT: usize = (1 << 24) - 1;
+
 pub type Lines = RunLength<usize>;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Chunk {
     code: Vec<u8>,
     constants: ValueArray,
@@ -42,6 +172,10 @@ impl Chunk {
         self.lines.push(line);
     }
 
+    pub fn patch(&mut self, offset: usize, bytes: &[u8]) {
+        self.code[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
     pub fn count(&self) -> usize {
         self.code.len()
     }
@@ -55,6 +189,36 @@ impl Chunk {
         self.constants.count() - 1
     }
 
+    /// Adds `value` to the constant pool and emits the instruction that loads
+    /// it, returning the constant's index.
+    pub fn write_constant(&mut self, value: Value, line: usize) -> usize {
+        let constant = self.add_constant(value);
+        self.write_with_constant(OpCode::Constant, constant, line);
+        constant
+    }
+
+    /// Emits `op_code` followed by its operand, switching to the long form of
+    /// the instruction when `operand` does not fit in a byte.
+    pub fn write_with_constant(&mut self, op_code: OpCode, operand: usize, line: usize) {
+        if let Ok(operand) = u8::try_from(operand) {
+            self.write(op_code, line);
+            self.write(operand, line);
+        } else {
+            assert!(
+                operand <= MAX_LONG_CONSTANT,
+                "Operand {operand} is too large."
+            );
+            let long = op_code
+                .long()
+                .unwrap_or_else(|| panic!("{op_code:?} has no long form."));
+            self.write(long, line);
+            let [_, high, middle, low] = (operand as u32).to_be_bytes();
+            for byte in [high, middle, low] {
+                self.write(byte, line);
+            }
+        }
+    }
+
     pub fn constants(&self) -> &ValueArray {
         &self.constants
     }
@@ -63,3 +227,40 @@ impl Chunk {
         &self.lines
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opcodes_round_trip_through_bytes() {
+        for op_code in OpCode::ALL {
+            assert_eq!(OpCode::try_from(u8::from(op_code)), Ok(op_code));
+        }
+        assert_eq!(
+            OpCode::try_from(OpCode::ALL.len() as u8),
+            Err(OpCode::ALL.len() as u8)
+        );
+    }
+
+    #[test]
+    fn write_constant_switches_to_the_long_form() {
+        let mut chunk = Chunk::new();
+        for i in 0..256 {
+            chunk.add_constant(Value::from(i as f64));
+        }
+        assert_eq!(chunk.write_constant(Value::from(1.5), 1), 256);
+        assert_eq!(chunk.code(), &[OpCode::ConstantLong as u8, 0, 1, 0]);
+        assert_eq!(chunk.lines()[3], 1);
+
+        chunk.write_with_constant(OpCode::GetGlobal, 255, 2);
+        assert_eq!(&chunk.code()[4..], &[OpCode::GetGlobal as u8, 255]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn byte_only_opcodes_have_no_long_form() {
+        let mut chunk = Chunk::new();
+        chunk.write_with_constant(OpCode::GetLocal, 256, 1);
+    }
+}
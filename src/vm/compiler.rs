@@ -0,0 +1,1372 @@
+use super::{
+    chunk::{Chunk, OpCode, MAX_LONG_CONSTANT},
+    memory::Heap,
+    object::{ObjFunction, ObjRef},
+    scanner::{Scanner, Token, TokenKind},
+    value::Value,
+};
+
+/// Marks the objects the caller keeps alive while the compiler allocates.
+pub type MarkRoots<'a> = &'a dyn Fn(&mut Heap);
+
+/// Compiles `source` into a script function allocated on `heap`.
+///
+/// The compiler may collect garbage, so everything the caller still needs
+/// must be marked by `mark_roots`.
+pub fn compile(source: &str, heap: &mut Heap, mark_roots: MarkRoots) -> Option<ObjRef> {
+    Compiler::new(source, heap, mark_roots).compile()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    None,
+    Assignment,
+    Ternary,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Self {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Ternary,
+            Precedence::Ternary => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+type ParseFn<'a> = fn(&mut Compiler<'a>, bool);
+
+struct ParseRule<'a> {
+    prefix: Option<ParseFn<'a>>,
+    infix: Option<ParseFn<'a>>,
+    precedence: Precedence,
+}
+
+impl<'a> ParseRule<'a> {
+    fn new(
+        prefix: Option<ParseFn<'a>>,
+        infix: Option<ParseFn<'a>>,
+        precedence: Precedence,
+    ) -> Self {
+        Self {
+            prefix,
+            infix,
+            precedence,
+        }
+    }
+}
+
+const LOCALS_MAX: usize = u8::MAX as usize + 1;
+
+struct Local<'a> {
+    name: Token<'a>,
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionType {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+impl FunctionType {
+    fn kind(self) -> &'static str {
+        match self {
+            FunctionType::Function => "function",
+            FunctionType::Initializer | FunctionType::Method => "method",
+            FunctionType::Script => "script",
+        }
+    }
+}
+
+/// Per-function compilation state; the compiler keeps a stack of these, one
+/// for every function declaration it is currently nested in.
+struct FunctionState<'a> {
+    function: ObjFunction,
+    function_type: FunctionType,
+    locals: Vec<Local<'a>>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+}
+
+impl<'a> FunctionState<'a> {
+    fn new(function_type: FunctionType, name: Option<ObjRef>) -> Self {
+        let mut locals = Vec::with_capacity(LOCALS_MAX);
+        // Slot zero holds the function being called, or the receiver in methods.
+        let slot_zero = match function_type {
+            FunctionType::Initializer | FunctionType::Method => "this",
+            FunctionType::Function | FunctionType::Script => "",
+        };
+        locals.push(Local {
+            name: Token::new(TokenKind::Identifier, slot_zero, 0),
+            depth: Some(0),
+            is_captured: false,
+        });
+        let function = match function_type {
+            FunctionType::Script => ObjFunction::script(),
+            _ => ObjFunction::new(name),
+        };
+        Self {
+            function,
+            function_type,
+            locals,
+            upvalues: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+}
+
+struct ClassState {
+    has_superclass: bool,
+}
+
+struct Compiler<'a> {
+    scanner: Scanner<'a>,
+    current: Token<'a>,
+    previous: Token<'a>,
+    had_error: bool,
+    panic_mode: bool,
+    heap: &'a mut Heap,
+    mark_roots: MarkRoots<'a>,
+    states: Vec<FunctionState<'a>>,
+    classes: Vec<ClassState>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(source: &'a str, heap: &'a mut Heap, mark_roots: MarkRoots<'a>) -> Self {
+        Self {
+            scanner: Scanner::new(source),
+            current: Token::new(TokenKind::Eof, "", 1),
+            previous: Token::new(TokenKind::Eof, "", 1),
+            had_error: false,
+            panic_mode: false,
+            heap,
+            mark_roots,
+            states: vec![FunctionState::new(FunctionType::Script, None)],
+            classes: Vec::new(),
+        }
+    }
+
+    fn compile(mut self) -> Option<ObjRef> {
+        self.advance();
+        while !self.match_token(TokenKind::Eof) {
+            self.declaration();
+        }
+        let function = self.end_compiler();
+        if self.had_error {
+            None
+        } else {
+            Some(function)
+        }
+    }
+
+    fn state(&self) -> &FunctionState<'a> {
+        self.states.last().expect("Compiler has no function state.")
+    }
+
+    fn state_mut(&mut self) -> &mut FunctionState<'a> {
+        self.states
+            .last_mut()
+            .expect("Compiler has no function state.")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state_mut().function.chunk
+    }
+
+    fn rule(kind: TokenKind) -> ParseRule<'a> {
+        match kind {
+            TokenKind::LeftParen => {
+                ParseRule::new(Some(Self::grouping), Some(Self::call), Precedence::Call)
+            }
+            TokenKind::Dot => ParseRule::new(None, Some(Self::dot), Precedence::Call),
+            TokenKind::Minus => {
+                ParseRule::new(Some(Self::unary), Some(Self::binary), Precedence::Term)
+            }
+            TokenKind::Plus => ParseRule::new(None, Some(Self::binary), Precedence::Term),
+            TokenKind::Slash => ParseRule::new(None, Some(Self::binary), Precedence::Factor),
+            TokenKind::Star => ParseRule::new(None, Some(Self::binary), Precedence::Factor),
+            TokenKind::Bang => ParseRule::new(Some(Self::unary), None, Precedence::None),
+            TokenKind::BangEqual | TokenKind::EqualEqual => {
+                ParseRule::new(None, Some(Self::binary), Precedence::Equality)
+            }
+            TokenKind::Greater
+            | TokenKind::GreaterEqual
+            | TokenKind::Less
+            | TokenKind::LessEqual => {
+                ParseRule::new(None, Some(Self::binary), Precedence::Comparison)
+            }
+            TokenKind::Identifier => ParseRule::new(Some(Self::variable), None, Precedence::None),
+            TokenKind::String => ParseRule::new(Some(Self::string), None, Precedence::None),
+            TokenKind::Number => ParseRule::new(Some(Self::number), None, Precedence::None),
+            TokenKind::QuestionMark => {
+                ParseRule::new(None, Some(Self::ternary), Precedence::Ternary)
+            }
+            TokenKind::And => ParseRule::new(None, Some(Self::and), Precedence::And),
+            TokenKind::Or => ParseRule::new(None, Some(Self::or), Precedence::Or),
+            TokenKind::False | TokenKind::Nil | TokenKind::True => {
+                ParseRule::new(Some(Self::literal), None, Precedence::None)
+            }
+            TokenKind::Fun => ParseRule::new(Some(Self::lambda), None, Precedence::None),
+            TokenKind::Super => ParseRule::new(Some(Self::super_), None, Precedence::None),
+            TokenKind::This => ParseRule::new(Some(Self::this), None, Precedence::None),
+            _ => ParseRule::new(None, None, Precedence::None),
+        }
+    }
+
+    fn declaration(&mut self) {
+        if self.match_token(TokenKind::Class) {
+            self.class_declaration();
+        } else if self.match_token(TokenKind::Fun) {
+            self.fun_declaration();
+        } else if self.match_token(TokenKind::Var) {
+            self.var_declaration();
+        } else {
+            self.statement();
+        }
+        if self.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    fn statement(&mut self) {
+        if self.match_token(TokenKind::For) {
+            self.for_statement();
+        } else if self.match_token(TokenKind::If) {
+            self.if_statement();
+        } else if self.match_token(TokenKind::Return) {
+            self.return_statement();
+        } else if self.match_token(TokenKind::While) {
+            self.while_statement();
+        } else if self.match_token(TokenKind::LeftBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    fn class_declaration(&mut self) {
+        self.consume(TokenKind::Identifier, "Expect class name.");
+        let class_name = self.previous;
+        let name_constant = self.identifier_constant(class_name);
+        self.declare_variable();
+
+        self.emit_with_constant(OpCode::Class, name_constant);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+
+        if self.match_token(TokenKind::Less) {
+            self.consume(TokenKind::Identifier, "Expect superclass name.");
+            self.variable(false);
+            if class_name.lexeme == self.previous.lexeme {
+                self.error("A class can't inherit from itself.");
+            }
+
+            self.begin_scope();
+            self.add_local(Token::new(TokenKind::Super, "super", self.previous.line));
+            self.define_variable(0);
+
+            self.named_variable(class_name, false);
+            self.emit_op(OpCode::Inherit);
+            if let Some(class) = self.classes.last_mut() {
+                class.has_superclass = true;
+            }
+        }
+
+        self.named_variable(class_name, false);
+        self.consume(TokenKind::LeftBrace, "Expect '{' before class body.");
+        while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
+            self.method();
+        }
+        self.consume(TokenKind::RightBrace, "Expect '}' after class body.");
+        self.emit_op(OpCode::Pop);
+
+        if self.classes.pop().is_some_and(|class| class.has_superclass) {
+            self.end_scope();
+        }
+    }
+
+    fn method(&mut self) {
+        self.consume(TokenKind::Identifier, "Expect method name.");
+        let name = self.previous;
+        let constant = self.identifier_constant(name);
+        let function_type = if name.lexeme == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+        let name = self.copy_string(name.lexeme);
+        self.function(function_type, Some(name));
+        self.emit_with_constant(OpCode::Method, constant);
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        self.mark_initialized();
+        let name = self.copy_string(self.previous.lexeme);
+        self.function(FunctionType::Function, Some(name));
+        self.define_variable(global);
+    }
+
+    fn function(&mut self, function_type: FunctionType, name: Option<ObjRef>) {
+        let kind = function_type.kind();
+        self.states.push(FunctionState::new(function_type, name));
+        self.begin_scope();
+
+        self.consume(
+            TokenKind::LeftParen,
+            &format!("Expect '(' after {kind} name."),
+        );
+        if !self.check(TokenKind::RightParen) {
+            loop {
+                self.state_mut().function.arity += 1;
+                if self.state().function.arity > 255 {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+                let constant = self.parse_variable("Expect parameter name.");
+                self.define_variable(constant);
+                if !self.match_token(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightParen, "Expect ')' after parameters.");
+        self.consume(
+            TokenKind::LeftBrace,
+            &format!("Expect '{{' before {kind} body."),
+        );
+        self.block();
+
+        let upvalues = self.state().upvalues.clone();
+        let function = self.end_compiler();
+        let constant = self.make_constant(Value::from(function));
+        self.emit_with_constant(OpCode::Closure, constant);
+        for upvalue in upvalues {
+            self.emit_byte(u8::from(upvalue.is_local));
+            self.emit_byte(upvalue.index);
+        }
+    }
+
+    fn var_declaration(&mut self) {
+        let global = self.parse_variable("Expect variable name.");
+        if self.match_token(TokenKind::Equal) {
+            self.expression();
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.consume(
+            TokenKind::Semicolon,
+            "Expect ';' after variable declaration.",
+        );
+        self.define_variable(global);
+    }
+
+    fn block(&mut self) {
+        while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
+            self.declaration();
+        }
+        self.consume(TokenKind::RightBrace, "Expect '}' after block.");
+    }
+
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'for'.");
+        if self.match_token(TokenKind::Var) {
+            self.var_declaration();
+        } else if !self.match_token(TokenKind::Semicolon) {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.chunk().count();
+        let exit_jump = if !self.match_token(TokenKind::Semicolon) {
+            self.expression();
+            self.consume(TokenKind::Semicolon, "Expect ';' after loop condition.");
+            let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+            self.emit_op(OpCode::Pop);
+            Some(exit_jump)
+        } else {
+            None
+        };
+
+        if !self.match_token(TokenKind::RightParen) {
+            let body_jump = self.emit_jump(OpCode::Jump);
+            let increment_start = self.chunk().count();
+            self.expression();
+            self.emit_op(OpCode::Pop);
+            self.consume(TokenKind::RightParen, "Expect ')' after for clauses.");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_op(OpCode::Pop);
+        }
+        self.end_scope();
+    }
+
+    fn if_statement(&mut self) {
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(TokenKind::RightParen, "Expect ')' after if condition.");
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.statement();
+        let else_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(then_jump);
+        self.emit_op(OpCode::Pop);
+        if self.match_token(TokenKind::Else) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.chunk().count();
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenKind::RightParen, "Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn return_statement(&mut self) {
+        if self.state().function_type == FunctionType::Script {
+            self.error("Can't return from top-level code.");
+        }
+
+        if self.match_token(TokenKind::Semicolon) {
+            self.emit_return();
+        } else {
+            if self.state().function_type == FunctionType::Initializer {
+                self.error("Can't return a value from an initializer.");
+            }
+            self.expression();
+            self.consume(TokenKind::Semicolon, "Expect ';' after return value.");
+            self.emit_op(OpCode::Return);
+        }
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        self.consume(TokenKind::Semicolon, "Expect ';' after expression.");
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::Assignment);
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        self.advance();
+        let Some(prefix_rule) = Self::rule(self.previous.kind).prefix else {
+            self.error("Expect expression");
+            return;
+        };
+        let can_assign = precedence <= Precedence::Assignment;
+        prefix_rule(self, can_assign);
+
+        while precedence <= Self::rule(self.current.kind).precedence {
+            self.advance();
+            if let Some(infix_rule) = Self::rule(self.previous.kind).infix {
+                infix_rule(self, can_assign);
+            }
+        }
+
+        if can_assign && self.match_token(TokenKind::Equal) {
+            self.error("Invalid assignment target.");
+        }
+    }
+
+    fn number(&mut self, _can_assign: bool) {
+        let value: f64 = self.previous.lexeme.parse().expect("Expected valid number");
+        self.emit_constant(Value::from(value));
+    }
+
+    fn string(&mut self, _can_assign: bool) {
+        let lexeme = self.previous.lexeme;
+        let string = self.copy_string(&lexeme[1..lexeme.len() - 1]);
+        self.emit_constant(Value::from(string));
+    }
+
+    fn literal(&mut self, _can_assign: bool) {
+        match self.previous.kind {
+            TokenKind::False => self.emit_op(OpCode::False),
+            TokenKind::Nil => self.emit_op(OpCode::Nil),
+            TokenKind::True => self.emit_op(OpCode::True),
+            _ => unreachable!(),
+        }
+    }
+
+    fn grouping(&mut self, _can_assign: bool) {
+        self.expression();
+        self.consume(TokenKind::RightParen, "Expect ')' after expression.");
+    }
+
+    fn unary(&mut self, _can_assign: bool) {
+        let operator = self.previous;
+        self.parse_precedence(Precedence::Unary);
+        match operator.kind {
+            TokenKind::Bang => self.emit_op_at(OpCode::Not, operator.line),
+            TokenKind::Minus => self.emit_op_at(OpCode::Negate, operator.line),
+            _ => unreachable!(),
+        }
+    }
+
+    fn binary(&mut self, _can_assign: bool) {
+        let operator = self.previous;
+        let rule = Self::rule(operator.kind);
+        self.parse_precedence(rule.precedence.next());
+        match operator.kind {
+            TokenKind::BangEqual => self.emit_ops_at(&[OpCode::Equal, OpCode::Not], operator.line),
+            TokenKind::EqualEqual => self.emit_op_at(OpCode::Equal, operator.line),
+            TokenKind::Greater => self.emit_op_at(OpCode::Greater, operator.line),
+            TokenKind::GreaterEqual => {
+                self.emit_ops_at(&[OpCode::Less, OpCode::Not], operator.line)
+            }
+            TokenKind::Less => self.emit_op_at(OpCode::Less, operator.line),
+            TokenKind::LessEqual => {
+                self.emit_ops_at(&[OpCode::Greater, OpCode::Not], operator.line)
+            }
+            TokenKind::Plus => self.emit_op_at(OpCode::Add, operator.line),
+            TokenKind::Minus => self.emit_op_at(OpCode::Subtract, operator.line),
+            TokenKind::Star => self.emit_op_at(OpCode::Multiply, operator.line),
+            TokenKind::Slash => self.emit_op_at(OpCode::Divide, operator.line),
+            _ => unreachable!(),
+        }
+    }
+
+    fn and(&mut self, _can_assign: bool) {
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.parse_precedence(Precedence::And);
+        self.patch_jump(end_jump);
+    }
+
+    fn or(&mut self, _can_assign: bool) {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(else_jump);
+        self.emit_op(OpCode::Pop);
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+    }
+
+    fn ternary(&mut self, _can_assign: bool) {
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.expression();
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.consume(TokenKind::Colon, "Expect ':'.");
+
+        self.patch_jump(then_jump);
+        self.emit_op(OpCode::Pop);
+        self.expression();
+        self.patch_jump(else_jump);
+    }
+
+    fn call(&mut self, _can_assign: bool) {
+        let arg_count = self.argument_list();
+        self.emit_op(OpCode::Call);
+        self.emit_byte(arg_count);
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count: usize = 0;
+        if !self.check(TokenKind::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == 255 {
+                    self.error("Can't have more than 255 arguments.");
+                }
+                arg_count += 1;
+                if !self.match_token(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightParen, "Expect ')' after arguments.");
+        arg_count.min(255) as u8
+    }
+
+    fn lambda(&mut self, _can_assign: bool) {
+        self.function(FunctionType::Function, None);
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(TokenKind::Identifier, "Expect property name after '.'.");
+        let name = self.identifier_constant(self.previous);
+
+        if can_assign && self.match_token(TokenKind::Equal) {
+            self.expression();
+            self.emit_with_constant(OpCode::SetProperty, name);
+        } else if self.match_token(TokenKind::LeftParen) {
+            let arg_count = self.argument_list();
+            self.emit_with_constant(OpCode::Invoke, name);
+            self.emit_byte(arg_count);
+        } else {
+            self.emit_with_constant(OpCode::GetProperty, name);
+        }
+    }
+
+    fn this(&mut self, _can_assign: bool) {
+        if self.classes.is_empty() {
+            self.error("Can't use 'this' outside of a class.");
+            return;
+        }
+        self.variable(false);
+    }
+
+    fn super_(&mut self, _can_assign: bool) {
+        match self.classes.last() {
+            None => self.error("Can't use 'super' outside of a class."),
+            Some(class) if !class.has_superclass => {
+                self.error("Can't use 'super' in a class with no superclass.");
+            }
+            Some(_) => {}
+        }
+
+        let keyword = self.previous;
+        self.consume(TokenKind::Dot, "Expect '.' after 'super'.");
+        self.consume(TokenKind::Identifier, "Expect superclass method name.");
+        let name = self.identifier_constant(self.previous);
+
+        self.named_variable(Token::new(TokenKind::This, "this", keyword.line), false);
+        let super_token = Token::new(TokenKind::Super, "super", keyword.line);
+        if self.match_token(TokenKind::LeftParen) {
+            let arg_count = self.argument_list();
+            self.named_variable(super_token, false);
+            self.emit_with_constant(OpCode::SuperInvoke, name);
+            self.emit_byte(arg_count);
+        } else {
+            self.named_variable(super_token, false);
+            self.emit_with_constant(OpCode::GetSuper, name);
+        }
+    }
+
+    fn variable(&mut self, can_assign: bool) {
+        self.named_variable(self.previous, can_assign);
+    }
+
+    fn named_variable(&mut self, name: Token<'a>, can_assign: bool) {
+        let current = self.states.len() - 1;
+        let (get_op, set_op, operand) = if let Some(slot) = self.resolve_local(current, name) {
+            (OpCode::GetLocal, OpCode::SetLocal, usize::from(slot))
+        } else if let Some(index) = self.resolve_upvalue(current, name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, usize::from(index))
+        } else {
+            let global = self.identifier_constant(name);
+            (OpCode::GetGlobal, OpCode::SetGlobal, global)
+        };
+
+        let op_code = if can_assign && self.match_token(TokenKind::Equal) {
+            self.expression();
+            set_op
+        } else {
+            get_op
+        };
+        self.chunk()
+            .write_with_constant(op_code, operand, name.line);
+    }
+
+    fn parse_variable(&mut self, message: &str) -> usize {
+        self.consume(TokenKind::Identifier, message);
+
+        self.declare_variable();
+        if self.state().scope_depth > 0 {
+            return 0;
+        }
+
+        self.identifier_constant(self.previous)
+    }
+
+    fn identifier_constant(&mut self, name: Token) -> usize {
+        let name = self.copy_string(name.lexeme);
+        self.make_constant(Value::from(name))
+    }
+
+    fn declare_variable(&mut self) {
+        if self.state().scope_depth == 0 {
+            return;
+        }
+
+        let name = self.previous;
+        let state = self.state();
+        let already_declared = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= state.scope_depth))
+            .any(|local| local.name.lexeme == name.lexeme);
+        if already_declared {
+            self.error("Already a variable with this name in this scope.");
+        }
+
+        self.add_local(name);
+    }
+
+    fn add_local(&mut self, name: Token<'a>) {
+        if self.state().locals.len() == LOCALS_MAX {
+            self.error("Too many local variables in function.");
+            return;
+        }
+        self.state_mut().locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+        });
+    }
+
+    fn resolve_local(&mut self, state: usize, name: Token) -> Option<u8> {
+        let (slot, local) = self.states[state]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name.lexeme == name.lexeme)?;
+        if local.depth.is_none() {
+            self.error("Can't read local variable in its own initializer.");
+        }
+        Some(slot as u8)
+    }
+
+    fn resolve_upvalue(&mut self, state: usize, name: Token) -> Option<u8> {
+        let enclosing = state.checked_sub(1)?;
+        if let Some(local) = self.resolve_local(enclosing, name) {
+            self.states[enclosing].locals[usize::from(local)].is_captured = true;
+            return Some(self.add_upvalue(state, local, true));
+        }
+        let upvalue = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(state, upvalue, false))
+    }
+
+    fn add_upvalue(&mut self, state: usize, index: u8, is_local: bool) -> u8 {
+        let upvalue = Upvalue { index, is_local };
+        let upvalues = &mut self.states[state].upvalues;
+        if let Some(existing) = upvalues.iter().position(|other| *other == upvalue) {
+            return existing as u8;
+        }
+        if upvalues.len() == LOCALS_MAX {
+            self.error("Too many closure variables in function.");
+            return 0;
+        }
+        upvalues.push(upvalue);
+        let function = &mut self.states[state].function;
+        function.upvalue_count += 1;
+        (function.upvalue_count - 1) as u8
+    }
+
+    fn define_variable(&mut self, global: usize) {
+        if self.state().scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+        self.emit_with_constant(OpCode::DefineGlobal, global);
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.state_mut();
+        if state.scope_depth == 0 {
+            return;
+        }
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(state.scope_depth);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.state_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.state_mut().scope_depth -= 1;
+
+        loop {
+            let state = self.state();
+            let Some(local) = state
+                .locals
+                .last()
+                .filter(|local| local.depth.is_none_or(|depth| depth > state.scope_depth))
+            else {
+                break;
+            };
+            if local.is_captured {
+                self.emit_op(OpCode::CloseUpvalue);
+            } else {
+                self.emit_op(OpCode::Pop);
+            }
+            self.state_mut().locals.pop();
+        }
+    }
+
+    fn end_compiler(&mut self) -> ObjRef {
+        self.emit_return();
+        self.collect_garbage_if_needed();
+        let state = self.states.pop().expect("Compiler has no function state.");
+        self.heap.new_function(state.function)
+    }
+
+    fn copy_string(&mut self, chars: &str) -> ObjRef {
+        self.collect_garbage_if_needed();
+        self.heap.copy_string(chars)
+    }
+
+    fn collect_garbage_if_needed(&mut self) {
+        if !self.heap.should_collect() {
+            return;
+        }
+        (self.mark_roots)(self.heap);
+        for state in self.states.iter() {
+            self.heap.mark_function(&state.function);
+        }
+        self.heap.collect_garbage();
+    }
+
+    fn emit_return(&mut self) {
+        if self.state().function_type == FunctionType::Initializer {
+            self.emit_op(OpCode::GetLocal);
+            self.emit_byte(0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let constant = self.make_constant(value);
+        self.emit_with_constant(OpCode::Constant, constant);
+    }
+
+    fn make_constant(&mut self, value: Value) -> usize {
+        let constant = self.chunk().add_constant(value);
+        if constant > MAX_LONG_CONSTANT {
+            self.error("Too many constants in one chunk.");
+            return 0;
+        }
+        constant
+    }
+
+    fn emit_op(&mut self, op_code: OpCode) {
+        self.emit_op_at(op_code, self.previous.line);
+    }
+
+    fn emit_op_at(&mut self, op_code: OpCode, line: usize) {
+        self.chunk().write(op_code, line);
+    }
+
+    fn emit_ops_at(&mut self, op_codes: &[OpCode], line: usize) {
+        for op_code in op_codes {
+            self.emit_op_at(*op_code, line);
+        }
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.previous.line;
+        self.chunk().write(byte, line);
+    }
+
+    fn emit_with_constant(&mut self, op_code: OpCode, constant: usize) {
+        let line = self.previous.line;
+        self.chunk().write_with_constant(op_code, constant, line);
+    }
+
+    fn emit_jump(&mut self, op_code: OpCode) -> usize {
+        self.emit_op(op_code);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        self.chunk().count() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        let jump = self.chunk().count() - offset - 2;
+        let jump = u16::try_from(jump).unwrap_or_else(|_| {
+            self.error("Too much code to jump over.");
+            u16::MAX
+        });
+        self.chunk().patch(offset, &jump.to_be_bytes());
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+
+        let offset = self.chunk().count() - loop_start + 2;
+        let offset = u16::try_from(offset).unwrap_or_else(|_| {
+            self.error("Loop body too large.");
+            u16::MAX
+        });
+        for byte in offset.to_be_bytes() {
+            self.emit_byte(byte);
+        }
+    }
+
+    fn advance(&mut self) {
+        self.previous = self.current;
+        loop {
+            self.current = self.scanner.scan_token();
+            if self.current.kind != TokenKind::Error {
+                break;
+            }
+            self.error_at_current(self.current.lexeme);
+        }
+    }
+
+    fn consume(&mut self, kind: TokenKind, message: &str) {
+        if self.check(kind) {
+            self.advance();
+        } else {
+            self.error_at_current(message);
+        }
+    }
+
+    fn check(&self, kind: TokenKind) -> bool {
+        self.current.kind == kind
+    }
+
+    fn match_token(&mut self, kind: TokenKind) -> bool {
+        if self.check(kind) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while self.current.kind != TokenKind::Eof {
+            if self.previous.kind == TokenKind::Semicolon {
+                return;
+            }
+            if matches!(
+                self.current.kind,
+                TokenKind::Class
+                    | TokenKind::Fun
+                    | TokenKind::Var
+                    | TokenKind::For
+                    | TokenKind::If
+                    | TokenKind::While
+                    | TokenKind::Return
+            ) {
+                return;
+            }
+            self.advance();
+        }
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        self.error_at(self.current, message);
+    }
+
+    fn error(&mut self, message: &str) {
+        self.error_at(self.previous, message);
+    }
+
+    fn error_at(&mut self, token: Token, message: &str) {
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+        match token.kind {
+            TokenKind::Eof => eprintln!("[line {}] Error at end: {message}", token.line),
+            TokenKind::Error => eprintln!("[line {}] Error: {message}", token.line),
+            _ => eprintln!(
+                "[line {}] Error at '{}': {message}",
+                token.line, token.lexeme
+            ),
+        }
+        self.had_error = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiles_arithmetic() {
+        let chunk = test_compile("-1 + 2 * 3;").unwrap();
+        assert_eq!(
+            chunk.code(),
+            &[
+                OpCode::Constant as u8,
+                0,
+                OpCode::Negate as u8,
+                OpCode::Constant as u8,
+                1,
+                OpCode::Constant as u8,
+                2,
+                OpCode::Multiply as u8,
+                OpCode::Add as u8,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn operators_are_emitted_at_their_line() {
+        let chunk = test_compile("1\n+\n2;").unwrap();
+        assert_eq!(chunk.lines()[4], 2);
+    }
+
+    #[test]
+    fn compiles_negated_comparisons() {
+        let chunk = test_compile("!(1 >= 2);").unwrap();
+        assert_eq!(
+            chunk.code(),
+            &[
+                OpCode::Constant as u8,
+                0,
+                OpCode::Constant as u8,
+                1,
+                OpCode::Less as u8,
+                OpCode::Not as u8,
+                OpCode::Not as u8,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert!(test_compile("1 +;").is_none());
+        assert!(test_compile("(1;").is_none());
+        assert!(test_compile("1").is_none());
+    }
+
+    #[test]
+    fn resolves_locals_to_slots() {
+        let chunk = test_compile("{ var a = 1; { var b = a; b = 2; } }").unwrap();
+        assert_eq!(
+            chunk.code(),
+            &[
+                OpCode::Constant as u8,
+                0,
+                OpCode::GetLocal as u8,
+                1,
+                OpCode::Constant as u8,
+                1,
+                OpCode::SetLocal as u8,
+                2,
+                OpCode::Pop as u8,
+                OpCode::Pop as u8,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_variable_errors() {
+        assert!(test_compile("{ var a = a; }").is_none());
+        assert!(test_compile("{ var a = 1; var a = 2; }").is_none());
+        assert!(test_compile("var a; var b; a + b = 1;").is_none());
+        assert!(test_compile("var a = 1; { var a = 2; { var a = a; } }").is_none());
+        assert!(test_compile("var a = 1; var a = a;").is_some());
+        assert!(test_compile("{ var a = 1; { var a = a; } }").is_none());
+        assert!(test_compile("{ var a = 1; { var b = a; var a = 2; } }").is_some());
+    }
+
+    #[test]
+    fn patches_jumps() {
+        let chunk = test_compile("if (true) 1; else 2;").unwrap();
+        assert_eq!(
+            chunk.code(),
+            &[
+                OpCode::True as u8,
+                OpCode::JumpIfFalse as u8,
+                0,
+                7,
+                OpCode::Pop as u8,
+                OpCode::Constant as u8,
+                0,
+                OpCode::Pop as u8,
+                OpCode::Jump as u8,
+                0,
+                4,
+                OpCode::Pop as u8,
+                OpCode::Constant as u8,
+                1,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn emits_loops_backwards() {
+        let chunk = test_compile("while (false) 1;").unwrap();
+        assert_eq!(
+            chunk.code(),
+            &[
+                OpCode::False as u8,
+                OpCode::JumpIfFalse as u8,
+                0,
+                7,
+                OpCode::Pop as u8,
+                OpCode::Constant as u8,
+                0,
+                OpCode::Pop as u8,
+                OpCode::Loop as u8,
+                0,
+                11,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_control_flow_errors() {
+        assert!(test_compile("if true) 1;").is_none());
+        assert!(test_compile("while (true 1;").is_none());
+        assert!(test_compile("for (;;").is_none());
+        assert!(test_compile("true ? 1;").is_none());
+    }
+
+    #[test]
+    fn compiles_functions_into_constants() {
+        let mut heap = Heap::new();
+        let script = compile("fun f(a) { return a; } f(1);", &mut heap, &|_| {}).unwrap();
+        let chunk = &script.as_function().unwrap().chunk;
+        assert_eq!(
+            chunk.code(),
+            &[
+                OpCode::Closure as u8,
+                1,
+                OpCode::DefineGlobal as u8,
+                0,
+                OpCode::GetGlobal as u8,
+                2,
+                OpCode::Constant as u8,
+                3,
+                OpCode::Call as u8,
+                1,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+
+        let Value::Obj(function) = chunk.constants()[1usize] else {
+            panic!("Expected function constant.");
+        };
+        let function = function.as_function().unwrap();
+        assert_eq!(function.arity, 1);
+        assert_eq!(function.to_string(), "<fn f>");
+        assert_eq!(
+            function.chunk.code(),
+            &[
+                OpCode::GetLocal as u8,
+                1,
+                OpCode::Return as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_upvalues() {
+        let mut heap = Heap::new();
+        let source = "fun outer() { var a = 1; fun middle() { fun inner() { a = 2; } } }";
+        let script = compile(source, &mut heap, &|_| {}).unwrap();
+        let outer = nested_function(&script.as_function().unwrap().chunk);
+        let outer = outer.as_function().unwrap();
+        assert_eq!(
+            outer.chunk.code(),
+            &[
+                OpCode::Constant as u8,
+                0,
+                OpCode::Closure as u8,
+                1,
+                1,
+                1,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+        let middle = nested_function(&outer.chunk);
+        let middle = middle.as_function().unwrap();
+        assert_eq!(middle.upvalue_count, 1);
+        assert_eq!(&middle.chunk.code()[..4], &[OpCode::Closure as u8, 0, 0, 0]);
+        let inner = nested_function(&middle.chunk);
+        let inner = inner.as_function().unwrap();
+        assert_eq!(inner.upvalue_count, 1);
+        assert_eq!(
+            &inner.chunk.code()[..4],
+            &[OpCode::Constant as u8, 0, OpCode::SetUpvalue as u8, 0]
+        );
+    }
+
+    #[test]
+    fn closes_captured_locals() {
+        let chunk = test_compile("{ var a = 1; fun f() { return a; } }").unwrap();
+        assert_eq!(
+            &chunk.code()[chunk.count() - 4..],
+            &[
+                OpCode::Pop as u8,
+                OpCode::CloseUpvalue as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_anonymous_functions() {
+        let chunk = test_compile("var f = fun (a) { return a; };").unwrap();
+        let Value::Obj(function) = chunk.constants()[1usize] else {
+            panic!("Expected function constant.");
+        };
+        assert_eq!(function.to_string(), "<anonymous fn>");
+        assert!(test_compile("fun (a) {};").is_none());
+
+        let mut heap = Heap::new();
+        let script = compile("var f = fun () {};", &mut heap, &|_| {}).unwrap();
+        assert_eq!(script.to_string(), "<script>");
+    }
+
+    #[test]
+    fn reports_function_errors() {
+        assert!(test_compile("return 1;").is_none());
+        assert!(test_compile("fun (a) {}").is_none());
+        assert!(test_compile("fun f(a, 1) {}").is_none());
+        assert!(test_compile("fun f(a) a;").is_none());
+        assert!(test_compile("f(1, 2;").is_none());
+        assert!(test_compile("fun f() { return; }").is_some());
+    }
+
+    #[test]
+    fn compiles_long_constants() {
+        let mut source: String = (0..200).map(|i| format!("var v{i} = {i};")).collect();
+        source.push_str("v0 = v1;");
+        let chunk = test_compile(&source).unwrap();
+        assert_eq!(chunk.constants().count(), 402);
+        assert_eq!(
+            &chunk.code()[chunk.count() - 11..],
+            &[
+                OpCode::GetGlobalLong as u8,
+                0,
+                1,
+                145,
+                OpCode::SetGlobalLong as u8,
+                0,
+                1,
+                144,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_invocations() {
+        let chunk = test_compile("var a; a.b(1);").unwrap();
+        assert_eq!(
+            &chunk.code()[3..],
+            &[
+                OpCode::GetGlobal as u8,
+                1,
+                OpCode::Constant as u8,
+                3,
+                OpCode::Invoke as u8,
+                2,
+                1,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_class_errors() {
+        assert!(test_compile("print(this);").is_none());
+        assert!(test_compile("fun f() { return this; }").is_none());
+        assert!(test_compile("super.a();").is_none());
+        assert!(test_compile("class A { f() { super.f(); } }").is_none());
+        assert!(test_compile("class A < A {}").is_none());
+        assert!(test_compile("class A { init() { return 1; } }").is_none());
+        assert!(test_compile("class A { init() { return; } }").is_some());
+        assert!(test_compile("class A { f() 1 }").is_none());
+        assert!(test_compile("class A < B { f() { super.f; } }").is_some());
+    }
+
+    #[test]
+    fn string_constants_are_interned() {
+        let mut heap = Heap::new();
+        let function = compile(r#""abc" + "abc";"#, &mut heap, &|_| {}).unwrap();
+        let chunk = &function.as_function().unwrap().chunk;
+        assert_eq!(chunk.constants()[0usize], chunk.constants()[1usize]);
+        assert_eq!(chunk.constants()[0usize].to_string(), "abc");
+    }
+
+    fn nested_function(chunk: &Chunk) -> ObjRef {
+        (0..chunk.constants().count())
+            .find_map(|index| match chunk.constants()[index] {
+                Value::Obj(object) if object.as_function().is_some() => Some(object),
+                _ => None,
+            })
+            .expect("Expected a nested function.")
+    }
+
+    fn test_compile(source: &str) -> Option<Chunk> {
+        let mut heap = Heap::new();
+        let function = compile(source, &mut heap, &|_| {})?;
+        Some(function.as_function().unwrap().chunk.clone())
+    }
+}
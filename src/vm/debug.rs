@@ -1,46 +1,355 @@
+use std::io::{self, Write};
+
 use crate::vm::chunk::OpCode;
 
-use super::chunk::Chunk;
+use super::{chunk::Chunk, value::Value};
 
 impl Chunk {
-    pub fn disassemble(&self, name: &str) {
-        println!("== {} ==", name);
+    /// Writes the disassembly of the chunk, followed by the chunks of the
+    /// functions in its constant pool.
+    pub fn disassemble(&self, name: &str, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "== {} ==", name)?;
 
         let mut offset = 0;
         while offset < self.count() {
-            offset = self.disassemble_instruction(offset);
+            offset = self.disassemble_instruction(offset, out)?;
+        }
+
+        for index in 0..self.constants().count() {
+            let Value::Obj(object) = self.constants()[index] else {
+                continue;
+            };
+            if let Some(function) = object.as_function() {
+                writeln!(out)?;
+                function.chunk.disassemble(&function.to_string(), out)?;
+            }
         }
+        Ok(())
     }
 
-    fn disassemble_instruction(&self, offset: usize) -> usize {
-        print!("{:04} ", offset);
+    /// Writes the instruction at `offset` and returns the offset of the next
+    /// one.
+    pub fn disassemble_instruction(&self, offset: usize, out: &mut dyn Write) -> io::Result<usize> {
+        write!(out, "{:04} ", offset)?;
         if offset > 0 && self.lines()[offset] == self.lines()[offset - 1] {
-            print!("   | ");
+            write!(out, "   | ")?;
         } else {
-            print!("{:4} ", self.lines()[offset]);
+            write!(out, "{:4} ", self.lines()[offset])?;
         }
 
         let instruction = self.code()[offset];
-        match instruction {
-            _ if instruction == OpCode::Return as u8 => Self::simple_instruction("RETURN", offset),
-            _ if instruction == OpCode::Constant as u8 => {
-                self.constant_instruction("CONSTANT", offset)
+        match OpCode::try_from(instruction) {
+            Ok(OpCode::Constant) => self.constant_instruction("CONSTANT", offset, out),
+            Ok(OpCode::ConstantLong) => {
+                self.constant_long_instruction("CONSTANT_LONG", offset, out)
+            }
+            Ok(OpCode::Nil) => Self::simple_instruction("NIL", offset, out),
+            Ok(OpCode::True) => Self::simple_instruction("TRUE", offset, out),
+            Ok(OpCode::False) => Self::simple_instruction("FALSE", offset, out),
+            Ok(OpCode::Equal) => Self::simple_instruction("EQUAL", offset, out),
+            Ok(OpCode::Greater) => Self::simple_instruction("GREATER", offset, out),
+            Ok(OpCode::Less) => Self::simple_instruction("LESS", offset, out),
+            Ok(OpCode::Pop) => Self::simple_instruction("POP", offset, out),
+            Ok(OpCode::GetLocal) => self.byte_instruction("GET_LOCAL", offset, out),
+            Ok(OpCode::SetLocal) => self.byte_instruction("SET_LOCAL", offset, out),
+            Ok(OpCode::DefineGlobal) => self.constant_instruction("DEFINE_GLOBAL", offset, out),
+            Ok(OpCode::DefineGlobalLong) => {
+                self.constant_long_instruction("DEFINE_GLOBAL_LONG", offset, out)
+            }
+            Ok(OpCode::GetGlobal) => self.constant_instruction("GET_GLOBAL", offset, out),
+            Ok(OpCode::GetGlobalLong) => {
+                self.constant_long_instruction("GET_GLOBAL_LONG", offset, out)
             }
-            _ => {
-                println!("Unknown opcode {}", instruction);
-                offset + 1
+            Ok(OpCode::SetGlobal) => self.constant_instruction("SET_GLOBAL", offset, out),
+            Ok(OpCode::SetGlobalLong) => {
+                self.constant_long_instruction("SET_GLOBAL_LONG", offset, out)
+            }
+            Ok(OpCode::GetUpvalue) => self.byte_instruction("GET_UPVALUE", offset, out),
+            Ok(OpCode::SetUpvalue) => self.byte_instruction("SET_UPVALUE", offset, out),
+            Ok(OpCode::GetProperty) => self.constant_instruction("GET_PROPERTY", offset, out),
+            Ok(OpCode::SetProperty) => self.constant_instruction("SET_PROPERTY", offset, out),
+            Ok(OpCode::GetSuper) => self.constant_instruction("GET_SUPER", offset, out),
+            Ok(OpCode::Add) => Self::simple_instruction("ADD", offset, out),
+            Ok(OpCode::Subtract) => Self::simple_instruction("SUBTRACT", offset, out),
+            Ok(OpCode::Multiply) => Self::simple_instruction("MULTIPLY", offset, out),
+            Ok(OpCode::Divide) => Self::simple_instruction("DIVIDE", offset, out),
+            Ok(OpCode::Not) => Self::simple_instruction("NOT", offset, out),
+            Ok(OpCode::Negate) => Self::simple_instruction("NEGATE", offset, out),
+            Ok(OpCode::Jump) => self.jump_instruction("JUMP", 1, offset, out),
+            Ok(OpCode::JumpIfFalse) => self.jump_instruction("JUMP_IF_FALSE", 1, offset, out),
+            Ok(OpCode::Loop) => self.jump_instruction("LOOP", -1, offset, out),
+            Ok(OpCode::Call) => self.byte_instruction("CALL", offset, out),
+            Ok(OpCode::Invoke) => self.invoke_instruction("INVOKE", false, offset, out),
+            Ok(OpCode::SuperInvoke) => self.invoke_instruction("SUPER_INVOKE", false, offset, out),
+            Ok(OpCode::Closure) => self.closure_instruction("CLOSURE", false, offset, out),
+            Ok(OpCode::CloseUpvalue) => Self::simple_instruction("CLOSE_UPVALUE", offset, out),
+            Ok(OpCode::Return) => Self::simple_instruction("RETURN", offset, out),
+            Ok(OpCode::Class) => self.constant_instruction("CLASS", offset, out),
+            Ok(OpCode::Inherit) => Self::simple_instruction("INHERIT", offset, out),
+            Ok(OpCode::Method) => self.constant_instruction("METHOD", offset, out),
+            Ok(OpCode::GetPropertyLong) => {
+                self.constant_long_instruction("GET_PROPERTY_LONG", offset, out)
+            }
+            Ok(OpCode::SetPropertyLong) => {
+                self.constant_long_instruction("SET_PROPERTY_LONG", offset, out)
+            }
+            Ok(OpCode::GetSuperLong) => {
+                self.constant_long_instruction("GET_SUPER_LONG", offset, out)
+            }
+            Ok(OpCode::InvokeLong) => self.invoke_instruction("INVOKE_LONG", true, offset, out),
+            Ok(OpCode::SuperInvokeLong) => {
+                self.invoke_instruction("SUPER_INVOKE_LONG", true, offset, out)
+            }
+            Ok(OpCode::ClosureLong) => self.closure_instruction("CLOSURE_LONG", true, offset, out),
+            Ok(OpCode::ClassLong) => self.constant_long_instruction("CLASS_LONG", offset, out),
+            Ok(OpCode::MethodLong) => self.constant_long_instruction("METHOD_LONG", offset, out),
+            Err(instruction) => {
+                writeln!(out, "Unknown opcode {}", instruction)?;
+                Ok(offset + 1)
             }
         }
     }
 
-    fn constant_instruction(&self, name: &str, offset: usize) -> usize {
+    fn constant_instruction(
+        &self,
+        name: &str,
+        offset: usize,
+        out: &mut dyn Write,
+    ) -> io::Result<usize> {
         let constant = self.code()[offset + 1];
-        println!("{:>16} {:4} {}", name, constant, self.constants()[constant]);
-        offset + 2
+        writeln!(
+            out,
+            "{:>16} {:4} {}",
+            name,
+            constant,
+            typed(self.constants()[constant])
+        )?;
+        Ok(offset + 2)
+    }
+
+    fn constant_long_instruction(
+        &self,
+        name: &str,
+        offset: usize,
+        out: &mut dyn Write,
+    ) -> io::Result<usize> {
+        let (constant, next) = self.constant_operand(offset, true);
+        writeln!(
+            out,
+            "{:>16} {:4} {}",
+            name,
+            constant,
+            typed(self.constants()[constant])
+        )?;
+        Ok(next)
+    }
+
+    fn invoke_instruction(
+        &self,
+        name: &str,
+        long: bool,
+        offset: usize,
+        out: &mut dyn Write,
+    ) -> io::Result<usize> {
+        let (constant, next) = self.constant_operand(offset, long);
+        let arg_count = self.code()[next];
+        writeln!(
+            out,
+            "{:>16} ({} args) {:4} {}",
+            name,
+            arg_count,
+            constant,
+            typed(self.constants()[constant])
+        )?;
+        Ok(next + 1)
+    }
+
+    fn closure_instruction(
+        &self,
+        name: &str,
+        long: bool,
+        offset: usize,
+        out: &mut dyn Write,
+    ) -> io::Result<usize> {
+        let (constant, next) = self.constant_operand(offset, long);
+        let function = self.constants()[constant];
+        writeln!(out, "{:>16} {:4} {}", name, constant, typed(function))?;
+
+        let upvalue_count = match function {
+            Value::Obj(object) => object.as_function().map_or(0, |f| f.upvalue_count),
+            _ => 0,
+        };
+        let mut offset = next;
+        for _ in 0..upvalue_count {
+            let is_local = self.code()[offset];
+            let index = self.code()[offset + 1];
+            let kind = if is_local == 1 { "local" } else { "upvalue" };
+            writeln!(
+                out,
+                "{:04}    |                   {} {}",
+                offset, kind, index
+            )?;
+            offset += 2;
+        }
+        Ok(offset)
+    }
+
+    /// Reads the constant index of the instruction at `offset`, returning it
+    /// with the offset just past it.
+    fn constant_operand(&self, offset: usize, long: bool) -> (usize, usize) {
+        if long {
+            let [high, middle, low] = [
+                self.code()[offset + 1],
+                self.code()[offset + 2],
+                self.code()[offset + 3],
+            ];
+            let constant = u32::from_be_bytes([0, high, middle, low]) as usize;
+            (constant, offset + 4)
+        } else {
+            (usize::from(self.code()[offset + 1]), offset + 2)
+        }
+    }
+
+    fn byte_instruction(
+        &self,
+        name: &str,
+        offset: usize,
+        out: &mut dyn Write,
+    ) -> io::Result<usize> {
+        let slot = self.code()[offset + 1];
+        writeln!(out, "{:>16} {:4}", name, slot)?;
+        Ok(offset + 2)
+    }
+
+    fn jump_instruction(
+        &self,
+        name: &str,
+        sign: isize,
+        offset: usize,
+        out: &mut dyn Write,
+    ) -> io::Result<usize> {
+        let jump = u16::from_be_bytes([self.code()[offset + 1], self.code()[offset + 2]]);
+        let target = offset as isize + 3 + sign * jump as isize;
+        writeln!(out, "{:>16} {:04} -> {:04}", name, offset, target)?;
+        Ok(offset + 3)
+    }
+
+    fn simple_instruction(name: &str, offset: usize, out: &mut dyn Write) -> io::Result<usize> {
+        writeln!(out, "{}", name)?;
+        Ok(offset + 1)
+    }
+}
+
+/// Formats a constant with its type, so that e.g. the number `1` and the
+/// string `"1"` can be told apart.
+fn typed(value: Value) -> String {
+    match value {
+        Value::Nil => String::from("nil"),
+        Value::Bool(value) => format!("bool {value}"),
+        Value::Number(value) => format!("number {value}"),
+        Value::Obj(object) => {
+            if let Some(string) = object.as_string() {
+                format!("string {:?}", string.as_str())
+            } else if object.as_function().is_some() {
+                format!("function {object}")
+            } else {
+                format!("object {object}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::{compiler, memory::Heap};
+
+    use super::*;
+
+    #[test]
+    fn disassembles_constants_with_their_types() {
+        let mut heap = Heap::new();
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::from(1.0), 1);
+        let one = heap.copy_string("1");
+        chunk.write_constant(Value::from(one), 1);
+        chunk.write(OpCode::Add, 2);
+        chunk.write(OpCode::Return, 2);
+
+        assert_eq!(
+            disassemble(&chunk),
+            "\
+== test ==
+0000    1         CONSTANT    0 number 1
+0002    |         CONSTANT    1 string \"1\"
+0004    2 ADD
+0005    | RETURN
+"
+        );
+    }
+
+    #[test]
+    fn disassembles_jumps_and_nested_functions() {
+        let mut heap = Heap::new();
+        let script =
+            compiler::compile("fun f(a) { while (a) a = false; }", &mut heap, &|_| {}).unwrap();
+        let chunk = &script.as_function().unwrap().chunk;
+
+        assert_eq!(
+            disassemble(chunk),
+            "\
+== test ==
+0000    1          CLOSURE    1 function <fn f>
+0002    |    DEFINE_GLOBAL    0 string \"f\"
+0004    | NIL
+0005    | RETURN
+
+== <fn f> ==
+0000    1        GET_LOCAL    1
+0002    |    JUMP_IF_FALSE 0002 -> 0013
+0005    | POP
+0006    | FALSE
+0007    |        SET_LOCAL    1
+0009    | POP
+0010    |             LOOP 0010 -> 0000
+0013    | POP
+0014    | NIL
+0015    | RETURN
+"
+        );
+    }
+
+    #[test]
+    fn disassembles_long_instructions() {
+        let mut source: String = (0..256).map(|i| format!("{i};")).collect();
+        source.push_str("var a; { var b; a = fun () { return b; }; } a.c(b);");
+        let mut heap = Heap::new();
+        let script = compiler::compile(&source, &mut heap, &|_| {}).unwrap();
+        let chunk = &script.as_function().unwrap().chunk;
+
+        let disassembly = disassemble(chunk);
+        let tail: Vec<_> = disassembly
+            .lines()
+            .skip_while(|line| !line.contains("CLOSURE_LONG"))
+            .take(8)
+            .collect();
+        assert_eq!(
+            tail,
+            [
+                "0774    |     CLOSURE_LONG  258 function <anonymous fn>",
+                "0778    |                   local 1",
+                "0780    |  SET_GLOBAL_LONG  257 string \"a\"",
+                "0784    | POP",
+                "0785    | CLOSE_UPVALUE",
+                "0786    |  GET_GLOBAL_LONG  259 string \"a\"",
+                "0790    |  GET_GLOBAL_LONG  261 string \"b\"",
+                "0794    |      INVOKE_LONG (1 args)  260 string \"c\"",
+            ]
+        );
     }
 
-    fn simple_instruction(name: &str, offset: usize) -> usize {
-        println!("{}", name);
-        offset + 1
+    fn disassemble(chunk: &Chunk) -> String {
+        let mut out = Vec::new();
+        chunk.disassemble("test", &mut out).unwrap();
+        String::from_utf8(out).unwrap()
     }
 }
@@ -0,0 +1,1561 @@
+use std::io::{self, Read, Write};
+
+use super::{
+    chunk::{Chunk, OpCode},
+    compiler,
+    memory::Heap,
+    native::{self, NativeFn},
+    object::{
+        ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjNative, ObjRef,
+        ObjUpvalue, UpvalueLocation,
+    },
+    serialize::LoadError,
+    table::Table,
+    value::Value,
+};
+
+// Deep enough for man-or-boy with k = 10.
+const FRAMES_MAX: usize = 2048;
+const FRAME_SLOTS: usize = u8::MAX as usize + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterpretResult {
+    Ok,
+    CompileError,
+    RuntimeError,
+}
+
+struct CallFrame {
+    closure: ObjRef,
+    ip: usize,
+    slots: usize,
+}
+
+impl CallFrame {
+    fn new(closure: ObjRef, slots: usize) -> Self {
+        Self {
+            closure,
+            ip: 0,
+            slots,
+        }
+    }
+
+    fn closure(&self) -> &ObjClosure {
+        self.closure
+            .as_closure()
+            .expect("Call frames hold closures.")
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.closure().function().chunk
+    }
+}
+
+pub struct VM<W> {
+    frames: Vec<CallFrame>,
+    stack: Vec<Value>,
+    /// Upvalues still pointing into the stack, sorted by slot.
+    open_upvalues: Vec<ObjRef>,
+    init_string: ObjRef,
+    /// The script last compiled or loaded, kept alive until the next one.
+    script: Option<ObjRef>,
+    heap: Heap,
+    globals: Table,
+    output: W,
+    /// Where to log each instruction before it runs, if anywhere.
+    trace: Option<Box<dyn Write>>,
+}
+
+impl<W> VM<W>
+where
+    W: Write,
+{
+    pub fn new_with_output(output: W) -> Self {
+        let mut heap = Heap::new();
+        let init_string = heap.copy_string("init");
+        let mut vm = Self {
+            frames: Vec::with_capacity(FRAMES_MAX),
+            stack: Vec::with_capacity(FRAME_SLOTS),
+            open_upvalues: Vec::new(),
+            init_string,
+            script: None,
+            heap,
+            globals: Table::new(),
+            output,
+            trace: None,
+        };
+        vm.define_native("clock", 0, native::clock);
+        vm.define_native("print", 1, native::print);
+        vm
+    }
+
+    /// Logs the stack and each instruction to `trace` before running it.
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.trace = trace;
+    }
+
+    pub fn interpret(&mut self, source: &str) -> InterpretResult {
+        if self.compile(source).is_some() {
+            self.execute()
+        } else {
+            InterpretResult::CompileError
+        }
+    }
+
+    /// Compiles `source` into the VM's script without running it, returning
+    /// the script's chunk.
+    pub fn compile(&mut self, source: &str) -> Option<&Chunk> {
+        self.script = None;
+        let globals = &self.globals;
+        let init_string = self.init_string;
+        let mark_roots = |heap: &mut Heap| {
+            heap.mark_table(globals);
+            heap.mark_object(init_string);
+        };
+        self.script = compiler::compile(source, &mut self.heap, &mark_roots);
+        self.script_chunk()
+    }
+
+    /// Loads a script written by [`Chunk::write_to`] and runs it.
+    pub fn interpret_compiled(
+        &mut self,
+        reader: &mut dyn Read,
+    ) -> Result<InterpretResult, LoadError> {
+        self.load(reader)?;
+        Ok(self.execute())
+    }
+
+    /// Loads a script written by [`Chunk::write_to`] into the VM without
+    /// running it, returning the script's chunk.
+    pub fn load(&mut self, reader: &mut dyn Read) -> Result<&Chunk, LoadError> {
+        self.script = None;
+        let mut function = ObjFunction::script();
+        function.chunk = Chunk::read_from(reader, &mut self.heap)?;
+        self.script = Some(self.heap.new_function(function));
+        Ok(self.script_chunk().expect("Just loaded."))
+    }
+
+    fn script_chunk(&self) -> Option<&Chunk> {
+        self.script
+            .as_ref()
+            .map(|script| &script.as_function().expect("Scripts are functions.").chunk)
+    }
+
+    /// Runs the script from the last successful [`VM::compile`] or
+    /// [`VM::load`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such script.
+    pub fn execute(&mut self) -> InterpretResult {
+        let script = self.script.expect("No script to execute.");
+        self.execute_function(script)
+    }
+
+    fn execute_function(&mut self, function: ObjRef) -> InterpretResult {
+        self.reset_stack();
+        self.push(Value::from(function));
+        self.collect_garbage_if_needed();
+        let closure = self
+            .heap
+            .new_closure(ObjClosure::new(function, Box::new([])));
+        self.pop();
+        self.push(Value::from(closure));
+        let result = self
+            .call_value(Value::from(closure), 0)
+            .and_then(|()| self.run());
+        match result {
+            Ok(()) => InterpretResult::Ok,
+            Err(message) => self.runtime_error(&message),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        loop {
+            if self.trace.is_some() {
+                self.trace_instruction()
+                    .map_err(|err| format!("Trace error: {err}"))?;
+            }
+            let instruction = self.read_byte();
+            match OpCode::try_from(instruction) {
+                Ok(OpCode::Constant) => {
+                    let constant = self.read_constant();
+                    self.push(constant);
+                }
+                Ok(OpCode::ConstantLong) => {
+                    let constant = self.read_constant_long();
+                    self.push(constant);
+                }
+                Ok(OpCode::Nil) => self.push(Value::Nil),
+                Ok(OpCode::True) => self.push(Value::from(true)),
+                Ok(OpCode::False) => self.push(Value::from(false)),
+                Ok(OpCode::Equal) => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Value::from(a == b));
+                }
+                Ok(OpCode::Pop) => {
+                    self.pop();
+                }
+                Ok(OpCode::GetLocal) => {
+                    let slot = self.frame().slots + usize::from(self.read_byte());
+                    self.push(self.stack[slot]);
+                }
+                Ok(OpCode::SetLocal) => {
+                    let slot = self.frame().slots + usize::from(self.read_byte());
+                    self.stack[slot] = self.peek(0);
+                }
+                Ok(OpCode::DefineGlobal) => {
+                    let name = self.read_string();
+                    self.define_global(name);
+                }
+                Ok(OpCode::DefineGlobalLong) => {
+                    let name = self.read_string_long();
+                    self.define_global(name);
+                }
+                Ok(OpCode::GetGlobal) => {
+                    let name = self.read_string();
+                    self.get_global(name)?;
+                }
+                Ok(OpCode::GetGlobalLong) => {
+                    let name = self.read_string_long();
+                    self.get_global(name)?;
+                }
+                Ok(OpCode::SetGlobal) => {
+                    let name = self.read_string();
+                    self.set_global(name)?;
+                }
+                Ok(OpCode::SetGlobalLong) => {
+                    let name = self.read_string_long();
+                    self.set_global(name)?;
+                }
+                Ok(OpCode::Greater) => self.binary_op(|a, b| a > b)?,
+                Ok(OpCode::Less) => self.binary_op(|a, b| a < b)?,
+                Ok(OpCode::GetUpvalue) => {
+                    let slot = usize::from(self.read_byte());
+                    let upvalue = self.frame().closure().upvalues[slot];
+                    self.push(self.upvalue_value(upvalue));
+                }
+                Ok(OpCode::SetUpvalue) => {
+                    let slot = usize::from(self.read_byte());
+                    let upvalue = self.frame().closure().upvalues[slot];
+                    self.set_upvalue_value(upvalue, self.peek(0));
+                }
+                Ok(OpCode::GetProperty) => {
+                    let name = self.read_string();
+                    self.get_property(name)?;
+                }
+                Ok(OpCode::GetPropertyLong) => {
+                    let name = self.read_string_long();
+                    self.get_property(name)?;
+                }
+                Ok(OpCode::SetProperty) => {
+                    let name = self.read_string();
+                    self.set_property(name)?;
+                }
+                Ok(OpCode::SetPropertyLong) => {
+                    let name = self.read_string_long();
+                    self.set_property(name)?;
+                }
+                Ok(OpCode::GetSuper) => {
+                    let name = self.read_string();
+                    self.get_super(name)?;
+                }
+                Ok(OpCode::GetSuperLong) => {
+                    let name = self.read_string_long();
+                    self.get_super(name)?;
+                }
+                Ok(OpCode::Add) => self.add()?,
+                Ok(OpCode::Subtract) => self.binary_op(|a, b| a - b)?,
+                Ok(OpCode::Multiply) => self.binary_op(|a, b| a * b)?,
+                Ok(OpCode::Divide) => self.binary_op(|a, b| a / b)?,
+                Ok(OpCode::Not) => {
+                    let value = self.pop();
+                    self.push(Value::from(!value.is_truthy()));
+                }
+                Ok(OpCode::Negate) => {
+                    let value = self
+                        .peek(0)
+                        .as_number()
+                        .ok_or_else(|| String::from("Operand must be a number."))?;
+                    self.pop();
+                    self.push(Value::from(-value));
+                }
+                Ok(OpCode::Jump) => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip += usize::from(offset);
+                }
+                Ok(OpCode::JumpIfFalse) => {
+                    let offset = self.read_short();
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip += usize::from(offset);
+                    }
+                }
+                Ok(OpCode::Loop) => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip -= usize::from(offset);
+                }
+                Ok(OpCode::Call) => {
+                    let arg_count = usize::from(self.read_byte());
+                    self.call_value(self.peek(arg_count), arg_count)?;
+                }
+                Ok(OpCode::Invoke) => {
+                    let method = self.read_string();
+                    let arg_count = usize::from(self.read_byte());
+                    self.invoke(method, arg_count)?;
+                }
+                Ok(OpCode::InvokeLong) => {
+                    let method = self.read_string_long();
+                    let arg_count = usize::from(self.read_byte());
+                    self.invoke(method, arg_count)?;
+                }
+                Ok(OpCode::SuperInvoke) => {
+                    let method = self.read_string();
+                    let arg_count = usize::from(self.read_byte());
+                    self.super_invoke(method, arg_count)?;
+                }
+                Ok(OpCode::SuperInvokeLong) => {
+                    let method = self.read_string_long();
+                    let arg_count = usize::from(self.read_byte());
+                    self.super_invoke(method, arg_count)?;
+                }
+                Ok(OpCode::Closure) => {
+                    let function = Self::expect_object(self.read_constant());
+                    self.closure(function);
+                }
+                Ok(OpCode::ClosureLong) => {
+                    let function = Self::expect_object(self.read_constant_long());
+                    self.closure(function);
+                }
+                Ok(OpCode::CloseUpvalue) => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                Ok(OpCode::Return) => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("Call frame stack underflow.");
+                    self.close_upvalues(frame.slots);
+                    if self.frames.is_empty() {
+                        self.pop();
+                        return Ok(());
+                    }
+                    self.stack.truncate(frame.slots);
+                    self.push(result);
+                }
+                Ok(OpCode::Class) => {
+                    let name = self.read_string();
+                    self.define_class(name);
+                }
+                Ok(OpCode::ClassLong) => {
+                    let name = self.read_string_long();
+                    self.define_class(name);
+                }
+                Ok(OpCode::Inherit) => {
+                    let superclass = Self::class(self.peek(1))?;
+                    let subclass = Self::class_or(self.peek(0), "Subclass must be a class.")?;
+                    let superclass = superclass.as_class().expect("Checked above.");
+                    subclass
+                        .as_class()
+                        .expect("Checked above.")
+                        .methods
+                        .borrow_mut()
+                        .add_all(&superclass.methods.borrow());
+                    self.pop();
+                }
+                Ok(OpCode::Method) => {
+                    let name = self.read_string();
+                    self.define_method(name)?;
+                }
+                Ok(OpCode::MethodLong) => {
+                    let name = self.read_string_long();
+                    self.define_method(name)?;
+                }
+                Err(instruction) => return Err(format!("Unknown opcode {instruction}.")),
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), String> {
+        if let Value::Obj(object) = callee {
+            if object.as_closure().is_some() {
+                return self.call(object, arg_count);
+            }
+            if let Some(native) = object.as_native() {
+                return self.call_native(native, arg_count);
+            }
+            if let Some(bound_method) = object.as_bound_method() {
+                let receiver_slot = self.stack.len() - arg_count - 1;
+                self.stack[receiver_slot] = bound_method.receiver;
+                return self.call(bound_method.method, arg_count);
+            }
+            if let Some(class) = object.as_class() {
+                let receiver_slot = self.stack.len() - arg_count - 1;
+                self.collect_garbage_if_needed();
+                let instance = self.heap.new_instance(ObjInstance::new(object));
+                self.stack[receiver_slot] = Value::from(instance);
+                let initializer = class.methods.borrow().get(self.init_string);
+                return match initializer {
+                    Some(Value::Obj(initializer)) => self.call(initializer, arg_count),
+                    _ => Self::check_arity(0, arg_count),
+                };
+            }
+        }
+        Err(String::from("Can only call functions and classes."))
+    }
+
+    fn call(&mut self, closure: ObjRef, arg_count: usize) -> Result<(), String> {
+        let arity = closure
+            .as_closure()
+            .ok_or_else(|| String::from("Can only call functions and classes."))?
+            .function()
+            .arity;
+        Self::check_arity(arity, arg_count)?;
+        if self.frames.len() == FRAMES_MAX {
+            return Err(String::from("Stack overflow."));
+        }
+        let slots = self.stack.len() - arg_count - 1;
+        self.frames.push(CallFrame::new(closure, slots));
+        Ok(())
+    }
+
+    fn call_native(&mut self, native: &ObjNative, arg_count: usize) -> Result<(), String> {
+        Self::check_arity(native.arity, arg_count)?;
+        let arguments_start = self.stack.len() - arg_count;
+        let result = (native.function)(&mut self.output, &self.stack[arguments_start..])?;
+        self.stack.truncate(arguments_start - 1);
+        self.push(result);
+        Ok(())
+    }
+
+    fn closure(&mut self, function: ObjRef) {
+        let upvalue_count = function
+            .as_function()
+            .expect("Closure operand is a function.")
+            .upvalue_count;
+        let mut upvalues = Vec::with_capacity(upvalue_count);
+        for _ in 0..upvalue_count {
+            let is_local = self.read_byte() == 1;
+            let index = usize::from(self.read_byte());
+            let upvalue = if is_local {
+                self.capture_upvalue(self.frame().slots + index)
+            } else {
+                self.frame().closure().upvalues[index]
+            };
+            upvalues.push(upvalue);
+        }
+        self.collect_garbage_if_needed();
+        let closure = self
+            .heap
+            .new_closure(ObjClosure::new(function, upvalues.into_boxed_slice()));
+        self.push(Value::from(closure));
+    }
+
+    fn define_class(&mut self, name: ObjRef) {
+        self.collect_garbage_if_needed();
+        let class = self.heap.new_class(ObjClass::new(name));
+        self.push(Value::from(class));
+    }
+
+    fn get_property(&mut self, name: ObjRef) -> Result<(), String> {
+        let instance = Self::instance(self.peek(0))?;
+        let instance = instance.as_instance().expect("Checked above.");
+        let field = instance.fields.borrow().get(name);
+        if let Some(value) = field {
+            self.pop();
+            self.push(value);
+            Ok(())
+        } else {
+            self.bind_method(instance.class, name)
+        }
+    }
+
+    fn set_property(&mut self, name: ObjRef) -> Result<(), String> {
+        let instance = Self::instance(self.peek(1))?;
+        instance
+            .as_instance()
+            .expect("Checked above.")
+            .fields
+            .borrow_mut()
+            .set(name, self.peek(0));
+        let value = self.pop();
+        self.pop();
+        self.push(value);
+        Ok(())
+    }
+
+    fn get_super(&mut self, name: ObjRef) -> Result<(), String> {
+        let superclass = Self::class(self.pop())?;
+        self.bind_method(superclass, name)
+    }
+
+    fn invoke(&mut self, name: ObjRef, arg_count: usize) -> Result<(), String> {
+        let receiver = self.peek(arg_count);
+        let instance = Self::instance(receiver)?;
+        let instance = instance.as_instance().expect("Checked above.");
+        let field = instance.fields.borrow().get(name);
+        if let Some(value) = field {
+            let receiver_slot = self.stack.len() - arg_count - 1;
+            self.stack[receiver_slot] = value;
+            return self.call_value(value, arg_count);
+        }
+        self.invoke_from_class(instance.class, name, arg_count)
+    }
+
+    fn super_invoke(&mut self, name: ObjRef, arg_count: usize) -> Result<(), String> {
+        let superclass = Self::class(self.pop())?;
+        self.invoke_from_class(superclass, name, arg_count)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+        arg_count: usize,
+    ) -> Result<(), String> {
+        let method = Self::find_method(class, name)?;
+        self.call(method, arg_count)
+    }
+
+    fn bind_method(&mut self, class: ObjRef, name: ObjRef) -> Result<(), String> {
+        let method = Self::find_method(class, name)?;
+        self.collect_garbage_if_needed();
+        let bound_method = self
+            .heap
+            .new_bound_method(ObjBoundMethod::new(self.peek(0), method));
+        self.pop();
+        self.push(Value::from(bound_method));
+        Ok(())
+    }
+
+    fn find_method(class: ObjRef, name: ObjRef) -> Result<ObjRef, String> {
+        let class = class.as_class().expect("Methods belong to classes.");
+        match class.methods.borrow().get(name) {
+            Some(Value::Obj(method)) => Ok(method),
+            _ => Err(Self::undefined_property(name)),
+        }
+    }
+
+    fn define_method(&mut self, name: ObjRef) -> Result<(), String> {
+        let method = self.peek(0);
+        let class = Self::class_or(self.peek(1), "Methods must be defined on a class.")?;
+        class
+            .as_class()
+            .expect("Checked above.")
+            .methods
+            .borrow_mut()
+            .set(name, method);
+        self.pop();
+        Ok(())
+    }
+
+    fn instance(value: Value) -> Result<ObjRef, String> {
+        value
+            .as_obj()
+            .filter(|object| object.as_instance().is_some())
+            .ok_or_else(|| String::from("Only instances have properties."))
+    }
+
+    fn class(value: Value) -> Result<ObjRef, String> {
+        Self::class_or(value, "Superclass must be a class.")
+    }
+
+    fn class_or(value: Value, message: &str) -> Result<ObjRef, String> {
+        value
+            .as_obj()
+            .filter(|object| object.as_class().is_some())
+            .ok_or_else(|| String::from(message))
+    }
+
+    fn check_arity(arity: usize, arg_count: usize) -> Result<(), String> {
+        if arity == arg_count {
+            Ok(())
+        } else {
+            Err(format!("Expected {arity} arguments but got {arg_count}."))
+        }
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let position = self
+            .open_upvalues
+            .partition_point(|upvalue| Self::open_slot(*upvalue) < slot);
+        if let Some(upvalue) = self.open_upvalues.get(position) {
+            if Self::open_slot(*upvalue) == slot {
+                return *upvalue;
+            }
+        }
+        self.collect_garbage_if_needed();
+        let upvalue = self.heap.new_upvalue(ObjUpvalue::new(slot));
+        self.open_upvalues.insert(position, upvalue);
+        upvalue
+    }
+
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last().copied() {
+            let slot = Self::open_slot(upvalue);
+            if slot < last {
+                break;
+            }
+            Self::as_upvalue(&upvalue)
+                .location
+                .set(UpvalueLocation::Closed(self.stack[slot]));
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn as_upvalue(upvalue: &ObjRef) -> &ObjUpvalue {
+        upvalue.as_upvalue().expect("Closures capture upvalues.")
+    }
+
+    fn open_slot(upvalue: ObjRef) -> usize {
+        match Self::as_upvalue(&upvalue).location.get() {
+            UpvalueLocation::Open(slot) => slot,
+            UpvalueLocation::Closed(_) => unreachable!("Closed upvalues are not in the open list."),
+        }
+    }
+
+    fn upvalue_value(&self, upvalue: ObjRef) -> Value {
+        match Self::as_upvalue(&upvalue).location.get() {
+            UpvalueLocation::Open(slot) => self.stack[slot],
+            UpvalueLocation::Closed(value) => value,
+        }
+    }
+
+    fn set_upvalue_value(&mut self, upvalue: ObjRef, value: Value) {
+        let upvalue = Self::as_upvalue(&upvalue);
+        match upvalue.location.get() {
+            UpvalueLocation::Open(slot) => self.stack[slot] = value,
+            UpvalueLocation::Closed(_) => upvalue.location.set(UpvalueLocation::Closed(value)),
+        }
+    }
+
+    fn define_native(&mut self, name: &'static str, arity: usize, function: NativeFn) {
+        self.collect_garbage_if_needed();
+        let key = self.heap.copy_string(name);
+        self.push(Value::from(key));
+        self.collect_garbage_if_needed();
+        let native = self.heap.new_native(ObjNative::new(name, arity, function));
+        self.push(Value::from(native));
+        self.globals.set(key, Value::from(native));
+        self.pop();
+        self.pop();
+    }
+
+    fn collect_garbage_if_needed(&mut self) {
+        if !self.heap.should_collect() {
+            return;
+        }
+        for value in self.stack.iter() {
+            self.heap.mark_value(*value);
+        }
+        for frame in self.frames.iter() {
+            self.heap.mark_object(frame.closure);
+        }
+        for upvalue in self.open_upvalues.iter() {
+            self.heap.mark_object(*upvalue);
+        }
+        self.heap.mark_table(&self.globals);
+        self.heap.mark_object(self.init_string);
+        if let Some(script) = self.script {
+            self.heap.mark_object(script);
+        }
+        self.heap.collect_garbage();
+    }
+
+    fn trace_instruction(&mut self) -> io::Result<()> {
+        let Some(trace) = self.trace.as_mut() else {
+            return Ok(());
+        };
+        write!(trace, "          ")?;
+        for value in self.stack.iter() {
+            write!(trace, "[ {value} ]")?;
+        }
+        writeln!(trace)?;
+        let frame = self.frames.last().expect("Call frame stack is empty.");
+        frame
+            .chunk()
+            .disassemble_instruction(frame.ip, trace.as_mut())?;
+        Ok(())
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("Call frame stack is empty.")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("Call frame stack is empty.")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.chunk().code()[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> u16 {
+        let high = self.read_byte();
+        let low = self.read_byte();
+        u16::from_be_bytes([high, low])
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_byte();
+        self.frame().chunk().constants()[index]
+    }
+
+    fn read_constant_long(&mut self) -> Value {
+        let [high, middle, low] = [self.read_byte(), self.read_byte(), self.read_byte()];
+        let index = u32::from_be_bytes([0, high, middle, low]) as usize;
+        self.frame().chunk().constants()[index]
+    }
+
+    fn read_string(&mut self) -> ObjRef {
+        let constant = self.read_constant();
+        Self::expect_string(constant)
+    }
+
+    fn read_string_long(&mut self) -> ObjRef {
+        let constant = self.read_constant_long();
+        Self::expect_string(constant)
+    }
+
+    fn expect_object(constant: Value) -> ObjRef {
+        match constant {
+            Value::Obj(object) => object,
+            value => panic!("Expected object constant, got {value}."),
+        }
+    }
+
+    fn expect_string(constant: Value) -> ObjRef {
+        match constant {
+            Value::Obj(object) if object.as_string().is_some() => object,
+            value => panic!("Expected string constant, got {value}."),
+        }
+    }
+
+    fn define_global(&mut self, name: ObjRef) {
+        self.globals.set(name, self.peek(0));
+        self.pop();
+    }
+
+    fn get_global(&mut self, name: ObjRef) -> Result<(), String> {
+        let value = self
+            .globals
+            .get(name)
+            .ok_or_else(|| Self::undefined_variable(name))?;
+        self.push(value);
+        Ok(())
+    }
+
+    fn set_global(&mut self, name: ObjRef) -> Result<(), String> {
+        if self.globals.set(name, self.peek(0)) {
+            self.globals.delete(name);
+            return Err(Self::undefined_variable(name));
+        }
+        Ok(())
+    }
+
+    fn undefined_variable(name: ObjRef) -> String {
+        format!("Undefined variable '{name}'.")
+    }
+
+    fn undefined_property(name: ObjRef) -> String {
+        format!("Undefined property '{name}'.")
+    }
+
+    fn add(&mut self) -> Result<(), String> {
+        if let (Some(b), Some(a)) = (self.peek(0).as_string(), self.peek(1).as_string()) {
+            let chars = format!("{a}{b}");
+            self.collect_garbage_if_needed();
+            let result = self.heap.take_string(chars);
+            self.pop();
+            self.pop();
+            self.push(Value::from(result));
+            Ok(())
+        } else if let (Some(b), Some(a)) = (self.peek(0).as_number(), self.peek(1).as_number()) {
+            self.pop();
+            self.pop();
+            self.push(Value::from(a + b));
+            Ok(())
+        } else {
+            Err(String::from("Operands must be two numbers or two string."))
+        }
+    }
+
+    fn binary_op<T>(&mut self, op: fn(f64, f64) -> T) -> Result<(), String>
+    where
+        Value: From<T>,
+    {
+        if let (Some(b), Some(a)) = (self.peek(0).as_number(), self.peek(1).as_number()) {
+            self.pop();
+            self.pop();
+            self.push(Value::from(op(a, b)));
+            Ok(())
+        } else {
+            Err(String::from("Operand must be numbers."))
+        }
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Stack underflow.")
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn reset_stack(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+    }
+
+    fn runtime_error(&mut self, message: &str) -> InterpretResult {
+        match self.frames.last() {
+            Some(frame) => {
+                let line = frame.chunk().lines()[frame.ip - 1];
+                eprintln!("{message}\n[line {line}]");
+            }
+            None => eprintln!("{message}"),
+        }
+        self.reset_stack();
+        InterpretResult::RuntimeError
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::vm::object::ObjFunction;
+
+    use super::*;
+
+    #[test]
+    fn arithmetic_works() {
+        let mut output = Vec::new();
+        let mut vm = VM::new_with_output(&mut output);
+        let mut function = ObjFunction::script();
+        let chunk = &mut function.chunk;
+        let print = vm.heap.copy_string("print");
+        let print = chunk.add_constant(Value::from(print));
+        chunk.write(OpCode::GetGlobal, 1);
+        chunk.write(print as u8, 1);
+        write_constant(chunk, Value::from(1.2));
+        write_constant(chunk, Value::from(3.4));
+        chunk.write(OpCode::Add, 1);
+        write_constant(chunk, Value::from(5.6));
+        chunk.write(OpCode::Divide, 1);
+        chunk.write(OpCode::Negate, 1);
+        chunk.write(OpCode::Call, 1);
+        chunk.write(1, 1);
+        chunk.write(OpCode::Pop, 1);
+        chunk.write(OpCode::Nil, 1);
+        chunk.write(OpCode::Return, 1);
+
+        let function = vm.heap.new_function(function);
+        assert_eq!(vm.execute_function(function), InterpretResult::Ok);
+        assert_eq!(output, format!("{}\n", -((1.2 + 3.4) / 5.6)).as_bytes());
+    }
+
+    #[test]
+    fn unknown_opcode_is_runtime_error() {
+        let mut output = Vec::new();
+        let mut vm = VM::new_with_output(&mut output);
+        let mut function = ObjFunction::script();
+        function.chunk.write(u8::MAX, 1);
+
+        let function = vm.heap.new_function(function);
+        assert_eq!(vm.execute_function(function), InterpretResult::RuntimeError);
+    }
+
+    #[test]
+    fn expressions_work() {
+        assert_prints("print(2 + 3);", b"5\n");
+        assert_prints("print(7 - 2 * 3);", b"1\n");
+        assert_prints("print((1 + 2) * 3);", b"9\n");
+        assert_prints("print(-(1 + 2) / 4);", b"-0.75\n");
+        assert_prints("1 + 2; print(1 - 2 - 3);", b"-4\n");
+    }
+
+    #[test]
+    fn logic_and_comparison_work() {
+        assert_prints("print(!(5 - 4 > 3 * 2 == !nil));", b"true\n");
+        assert_prints("print(nil);", b"nil\n");
+        assert_prints("print(!0);", b"false\n");
+        assert_prints("print(1 <= 1);", b"true\n");
+        assert_prints("print(1 >= 2);", b"false\n");
+        assert_prints("print(1 != 2);", b"true\n");
+        assert_prints("print(nil == false);", b"false\n");
+    }
+
+    #[test]
+    fn strings_work() {
+        assert_prints(r#"print("ala" + " ma " + "kota");"#, b"ala ma kota\n");
+        assert_prints(r#"print("ab" == "a" + "b");"#, b"true\n");
+        assert_prints(r#"print("ab" != "ba");"#, b"true\n");
+        assert_prints(r#"print("" == nil);"#, b"false\n");
+    }
+
+    #[test]
+    fn define_var_works() {
+        assert_prints("var a = 1; var b = 2; print(a + b);", b"3\n");
+        assert_prints("var a; print(a);", b"nil\n");
+        assert_prints("var a = 1; var a = a + 1; print(a);", b"2\n");
+    }
+
+    #[test]
+    fn assignment_works() {
+        assert_prints("var a = 1; print(a); a = 2; print(a);", b"1\n2\n");
+        assert_prints("var a; var b; a = b = 3; print(a + b);", b"6\n");
+        assert_prints("{ var a = 1; a = a + 1; print(a); }", b"2\n");
+    }
+
+    #[test]
+    fn block_works() {
+        assert_prints(
+            r#"
+            var a = "global a";
+            var b = "global b";
+            var c = "global c";
+            {
+                var a = "outer a";
+                var b = "outer b";
+                {
+                    var a = "inner a";
+                    print(a);
+                    print(b);
+                    print(c);
+                }
+                print(a);
+                print(b);
+                print(c);
+            }
+            print(a);
+            print(b);
+            print(c);
+        "#,
+            b"inner a\nouter b\nglobal c\nouter a\nouter b\nglobal c\nglobal a\nglobal b\nglobal c\n",
+        );
+    }
+
+    #[test]
+    fn logical_or_works() {
+        assert_prints(
+            r#"
+            print("hi" or 2);
+            print(nil or "yes");
+            print(nil or false or 5 or 6);
+        "#,
+            b"hi\nyes\n5\n",
+        )
+    }
+
+    #[test]
+    fn logical_and_works() {
+        assert_prints(
+            r#"
+            print("hi" and 2);
+            print(nil and "yes");
+            print(false and nil and 5 and 6);
+            print(3 and 4 and 5 and 6);
+        "#,
+            b"2\nnil\nfalse\n6\n",
+        )
+    }
+
+    #[test]
+    fn ternary_works() {
+        assert_prints("print(2 < 3 ? 2 * 3 : 2 + 3);", b"6\n");
+        assert_prints("print(2 > 3 ? 2 * 3 : 2 + 3);", b"5\n");
+        assert_prints("print(false ? 1 : nil ? 2 : 3);", b"3\n");
+        assert_prints("var a; a = 3 ? 4 : 5; print(a);", b"4\n");
+    }
+
+    #[test]
+    fn if_stmt_works() {
+        assert_prints(
+            r#"
+            if (true) print("yes"); else print("no");
+            if (0) print("yes"); else print("no");
+            if (nil) print("yes"); else print("no");
+            if (false) print("yes");
+            if (true)
+                if (false) print("thenTrueFalse");
+                else print("elseTrueFalse");
+        "#,
+            b"yes\nyes\nno\nelseTrueFalse\n",
+        )
+    }
+
+    #[test]
+    fn while_stmt_works() {
+        assert_prints(
+            r#"
+            var n = 5;
+            var f = 1;
+            while (n > 0) {
+                f = f * n;
+                n = n - 1;
+            }
+            print(f);
+        "#,
+            b"120\n",
+        );
+    }
+
+    #[test]
+    fn for_stmt_works() {
+        assert_prints(
+            r#"
+            var a = 0;
+            var temp;
+
+            for (var b = 1; a < 100; b = temp + b) {
+                print(a);
+                temp = a;
+                a = b;
+            }
+        "#,
+            b"0\n1\n1\n2\n3\n5\n8\n13\n21\n34\n55\n89\n",
+        );
+        assert_prints("var i = 0; for (; i < 3;) i = i + 1; print(i);", b"3\n");
+    }
+
+    #[test]
+    fn undefined_variables_are_runtime_errors() {
+        assert_eq!(test_interpret("print(x);").0, InterpretResult::RuntimeError);
+        assert_eq!(test_interpret("x = 1;").0, InterpretResult::RuntimeError);
+        assert_prints_after_error("x = 1;", "print(x);");
+    }
+
+    #[test]
+    fn globals_survive_between_runs() {
+        let mut output = Vec::new();
+        let mut vm = VM::new_with_output(&mut output);
+        assert_eq!(vm.interpret("var a = 1;"), InterpretResult::Ok);
+        assert_eq!(vm.interpret("print(a);"), InterpretResult::Ok);
+        assert_eq!(output, b"1\n");
+    }
+
+    #[test]
+    fn type_errors_are_runtime_errors() {
+        assert_eq!(test_interpret("-true;").0, InterpretResult::RuntimeError);
+        assert_eq!(test_interpret("1 < nil;").0, InterpretResult::RuntimeError);
+        assert_eq!(
+            test_interpret("1 + false;").0,
+            InterpretResult::RuntimeError
+        );
+    }
+
+    #[test]
+    fn functions_work() {
+        assert_prints(
+            r#"
+            fun fib(n) {
+                if (n < 2) return n;
+                return fib(n - 2) + fib(n - 1);
+            }
+            print(fib(10));
+        "#,
+            b"55\n",
+        );
+        assert_prints(
+            r#"
+            fun sum(a, b, c) {
+                var d = a + b;
+                return d + c;
+            }
+            print(sum(1, 2, 3));
+        "#,
+            b"6\n",
+        );
+        assert_prints("fun f() {} print(f());", b"nil\n");
+        assert_prints("fun f() { return; } print(f);", b"<fn f>\n");
+        assert_prints("{ fun f(a) { return -a; } print(f(2)); }", b"-2\n");
+    }
+
+    #[test]
+    fn local_functions_and_closures_work() {
+        assert_prints(
+            r#"
+            fun makeCounter() {
+                var i = 0;
+                fun count() {
+                    i = i + 1;
+                    print(i);
+                }
+
+                return count;
+            }
+
+            var counter = makeCounter();
+            counter();
+            counter();
+        "#,
+            b"1\n2\n",
+        );
+    }
+
+    #[test]
+    fn closures_share_and_close_upvalues() {
+        assert_prints(
+            r#"
+            var get;
+            var set;
+            {
+                var a = "initial";
+                fun g() { return a; }
+                fun s(value) { a = value; }
+                get = g;
+                set = s;
+                set("updated");
+                print(a);
+            }
+            print(get());
+            set("closed");
+            print(get());
+        "#,
+            b"updated\nupdated\nclosed\n",
+        );
+        assert_prints(
+            r#"
+            var fns;
+            for (var i = 0; i < 2; i = i + 1) {
+                var j = i;
+                fun f() { return j; }
+                if (i == 0) fns = f;
+            }
+            print(fns());
+        "#,
+            b"0\n",
+        );
+        assert_prints(
+            r#"
+            fun outer() {
+                var x = "outside";
+                fun middle() {
+                    fun inner() { return x; }
+                    return inner;
+                }
+                return middle;
+            }
+            print(outer()()());
+        "#,
+            b"outside\n",
+        );
+    }
+
+    #[test]
+    fn lambda_works() {
+        assert_prints(
+            r#"
+            fun thrice(fn) {
+                for (var i = 1; i<= 3; i = i + 1) {
+                    fn(i);
+                }
+            }
+
+            thrice(fun (a) {
+                print(a);
+            });
+        "#,
+            b"1\n2\n3\n",
+        );
+        assert_prints("print(fun () {});", b"<anonymous fn>\n");
+    }
+
+    #[test]
+    fn long_constants_work() {
+        let mut source: String = (0..200).map(|i| format!("var v{i} = {i};")).collect();
+        source.push_str("v0 = v199 + 0.5; print(v0);");
+        assert_prints(&source, b"199.5\n");
+    }
+
+    #[test]
+    fn functions_and_classes_work_after_many_constants() {
+        let padding: String = (0..300).map(|i| format!("{i};")).collect();
+        let source = r#"
+            PADDING
+            fun counter() {
+                var count = 0;
+                fun increment() {
+                    count = count + 1;
+                    return count;
+                }
+                return increment;
+            }
+
+            class A {
+                init(n) { this.n = n; }
+                get() { return this.n; }
+            }
+
+            class B < A {
+                get() { PADDING return super.get() + 1; }
+                bound() { PADDING return super.get; }
+            }
+
+            var next = counter();
+            next();
+            var b = B(next());
+            b.n = b.n + 10;
+            print(b.get());
+            print(b.bound()());
+        "#
+        .replace("PADDING", &padding);
+        assert_prints(&source, b"13\n12\n");
+
+        let mut bytes = Vec::new();
+        let mut output = Vec::new();
+        let mut vm = VM::new_with_output(&mut output);
+        let chunk = vm.compile(&source).unwrap();
+        assert!(chunk.code().contains(&(OpCode::ClosureLong as u8)));
+        assert!(chunk.code().contains(&(OpCode::ClassLong as u8)));
+        chunk.write_to(&mut bytes).unwrap();
+        let result = vm.interpret_compiled(&mut bytes.as_slice()).unwrap();
+        assert_eq!(result, InterpretResult::Ok);
+        drop(vm);
+        assert_eq!(output, b"13\n12\n");
+    }
+
+    #[test]
+    fn compiled_scripts_run() {
+        let mut bytes = Vec::new();
+        let mut output = Vec::new();
+        let mut vm = VM::new_with_output(&mut output);
+        vm.compile("fun add(a, b) { return a + b; } print(add(1, 2));")
+            .unwrap()
+            .write_to(&mut bytes)
+            .unwrap();
+        drop(vm);
+
+        let mut vm = VM::new_with_output(&mut output);
+        let result = vm.interpret_compiled(&mut bytes.as_slice()).unwrap();
+        assert_eq!(result, InterpretResult::Ok);
+        drop(vm);
+        assert_eq!(output, b"3\n");
+    }
+
+    #[test]
+    fn compiled_scripts_survive_garbage_collection() {
+        let mut output = Vec::new();
+        let mut vm = VM::new_with_output(&mut output);
+        assert!(vm.compile(r#"print("a" + "b");"#).is_some());
+        vm.collect_garbage_if_needed();
+        assert_eq!(vm.execute(), InterpretResult::Ok);
+        assert_eq!(vm.execute(), InterpretResult::Ok);
+        drop(vm);
+        assert_eq!(output, b"ab\nab\n");
+    }
+
+    #[test]
+    fn man_or_boy() {
+        assert_prints(
+            r#"
+            fun a(k, x1, x2, x3, x4, x5) {
+                fun b() {
+                  k = k - 1;
+                  return a(k, b, x1, x2, x3, x4);
+                }
+                return (k > 0) ? b() : x4() + x5();
+              }
+
+            fun x(n) {
+                return fun () {
+                  return n;
+                };
+            }
+
+            print(a(10, x(1), x(-1), x(-1), x(1), x(0)));
+        "#,
+            b"-67\n",
+        );
+    }
+
+    #[test]
+    fn classes_work() {
+        assert_prints(
+            r#"
+            class DevonshireCream {
+                serveOn() {
+                    return "Scones";
+                }
+            }
+
+            print(DevonshireCream);
+            print(DevonshireCream());
+        "#,
+            b"DevonshireCream\nDevonshireCream instance\n",
+        );
+    }
+
+    #[test]
+    fn fields_and_methods_work() {
+        assert_prints(
+            r#"
+            class Cake {
+                taste() {
+                    var adjective = "delicious";
+                    print("The " + this.flavor + " cake is " + adjective + "!");
+                }
+            }
+
+            var cake = Cake();
+            cake.flavor = "German chocolate";
+            cake.taste();
+            var taste = cake.taste;
+            cake.flavor = "lemon";
+            taste();
+            print(taste);
+        "#,
+            b"The German chocolate cake is delicious!\nThe lemon cake is delicious!\n<fn taste>\n",
+        );
+        assert_prints(
+            r#"
+            class Box {}
+            fun shout() { return "field"; }
+            var box = Box();
+            box.method = shout;
+            print(box.method());
+        "#,
+            b"field\n",
+        );
+    }
+
+    #[test]
+    fn constructor_works() {
+        assert_prints(
+            r#"
+            class Rectangle {
+                init(a, b) {
+                    this.a = a;
+                    this.b = b;
+                }
+
+                area() {
+                    return this.a * this.b;
+                }
+            }
+
+            var rect = Rectangle(10, 20);
+            print(rect.area());
+            print(rect.init(1, 2).area());
+        "#,
+            b"200\n2\n",
+        );
+        assert_prints(
+            r#"
+            class Early {
+                init() {
+                    this.value = 1;
+                    return;
+                    this.value = 2;
+                }
+            }
+            print(Early().value);
+        "#,
+            b"1\n",
+        );
+    }
+
+    #[test]
+    fn super_works() {
+        assert_prints(
+            r#"
+            class Doughnut {
+                cook() {
+                    print("Fry until golden brown.");
+                }
+            }
+
+            class BostonCream < Doughnut {
+                cook() {
+                    super.cook();
+                    print("Pipe full of custard and coat with chocolate.");
+                }
+            }
+
+            BostonCream().cook();
+        "#,
+            b"Fry until golden brown.\nPipe full of custard and coat with chocolate.\n",
+        );
+        assert_prints(
+            r#"
+            class A {
+                method() {
+                    print("A method");
+                }
+            }
+
+            class B < A {
+                method() {
+                    print("B method");
+                }
+
+                test() {
+                    var method = super.method;
+                    method();
+                }
+            }
+
+            class C < B {}
+
+            C().test();
+        "#,
+            b"A method\n",
+        );
+    }
+
+    #[test]
+    fn class_errors_are_runtime_errors() {
+        assert_eq!(
+            test_interpret("var a = 1; a.b;").0,
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            test_interpret("var a = 1; a.b = 2;").0,
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            test_interpret("var a = 1; a.b();").0,
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            test_interpret("class A {} A().b;").0,
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            test_interpret("class A {} A(1);").0,
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            test_interpret("var B = 1; class A < B {}").0,
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(
+            test_interpret("class A { init(a) {} } A();").0,
+            InterpretResult::RuntimeError
+        );
+    }
+
+    #[test]
+    fn natives_work() {
+        assert_prints("print(print);", b"<native fn>\n");
+        assert_prints("var a = clock(); print(a - a);", b"0\n");
+        assert_prints("print(print(1));", b"1\nnil\n");
+    }
+
+    #[test]
+    fn call_errors_are_runtime_errors() {
+        assert_eq!(
+            test_interpret("fun f(a) {} f();").0,
+            InterpretResult::RuntimeError
+        );
+        assert_eq!(test_interpret("clock(1);").0, InterpretResult::RuntimeError);
+        assert_eq!(test_interpret("\"f\"();").0, InterpretResult::RuntimeError);
+        assert_eq!(
+            test_interpret("fun f() { f(); } f();").0,
+            InterpretResult::RuntimeError
+        );
+        assert_prints_after_error("fun f() { f(); } f();", "f();");
+    }
+
+    #[test]
+    fn garbage_is_collected() {
+        let mut output = Vec::new();
+        let mut vm = VM::new_with_output(&mut output);
+        let source = r#"
+            var a = "";
+            for (var i = 0; i < 100; i = i + 1) a = a + "x";
+            print(a == "x" + a);
+        "#;
+        assert_eq!(vm.interpret(source), InterpretResult::Ok);
+        // Without collection the intermediate strings alone take 5050 bytes.
+        assert!(vm.heap.bytes_allocated() < 5050);
+    }
+
+    #[test]
+    fn execution_is_traced() {
+        let trace = SharedBuffer::default();
+        let mut output = Vec::new();
+        let mut vm = VM::new_with_output(&mut output);
+        vm.set_trace(Some(Box::new(trace.clone())));
+        assert_eq!(vm.interpret("-1 + 2;"), InterpretResult::Ok);
+        assert_eq!(
+            String::from_utf8(trace.0.take()).unwrap(),
+            "          [ <script> ]
+0000    1         CONSTANT    0 number 1
+          [ <script> ][ 1 ]
+0002    | NEGATE
+          [ <script> ][ -1 ]
+0003    |         CONSTANT    1 number 2
+          [ <script> ][ -1 ][ 2 ]
+0005    | ADD
+          [ <script> ][ 1 ]
+0006    | POP
+          [ <script> ]
+0007    | NIL
+          [ <script> ][ nil ]
+0008    | RETURN
+"
+        );
+    }
+
+    #[test]
+    fn compile_error_is_reported() {
+        assert_eq!(
+            test_interpret("print(1 +);").0,
+            InterpretResult::CompileError
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_constant(chunk: &mut Chunk, value: Value) {
+        let constant = chunk.add_constant(value);
+        chunk.write(OpCode::Constant, 1);
+        chunk.write(constant as u8, 1);
+    }
+
+    fn assert_prints(source: &str, value: &[u8]) {
+        let (result, output) = test_interpret(source);
+        assert_eq!(result, InterpretResult::Ok);
+        assert_eq!(
+            output,
+            value,
+            "\nLeft: {}\n, right: \n{}",
+            String::from_utf8(output.clone()).unwrap(),
+            String::from_utf8(Vec::from(value)).unwrap()
+        );
+    }
+
+    fn assert_prints_after_error(failing: &str, source: &str) {
+        let mut output = Vec::new();
+        let mut vm = VM::new_with_output(&mut output);
+        assert_eq!(vm.interpret(failing), InterpretResult::RuntimeError);
+        assert_eq!(vm.interpret(source), InterpretResult::RuntimeError);
+    }
+
+    fn test_interpret(source: &str) -> (InterpretResult, Vec<u8>) {
+        let mut output = Vec::new();
+        let mut vm = VM::new_with_output(&mut output);
+        let result = vm.interpret(source);
+        (result, output)
+    }
+}
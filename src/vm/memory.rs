@@ -0,0 +1,304 @@
+use std::{mem, ptr::NonNull};
+
+use super::{
+    object::{
+        self, Obj, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjKind,
+        ObjNative, ObjRef, ObjString, ObjUpvalue, UpvalueLocation,
+    },
+    table::Table,
+    value::Value,
+};
+
+const GC_HEAP_GROW_FACTOR: usize = 2;
+const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+
+/// Owner of every VM object.
+///
+/// The heap never collects on its own: callers check [`Heap::should_collect`]
+/// before allocating, mark their roots and then call
+/// [`Heap::collect_garbage`].
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<ObjRef>,
+    strings: Table,
+    gray_stack: Vec<ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    stress: bool,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            strings: Table::new(),
+            gray_stack: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+            stress: cfg!(any(test, feature = "stress_gc")),
+        }
+    }
+
+    pub fn copy_string(&mut self, chars: &str) -> ObjRef {
+        let hash = object::hash_string(chars);
+        if let Some(interned) = self.strings.find_string(chars, hash) {
+            interned
+        } else {
+            self.intern(ObjString::new(Box::from(chars)))
+        }
+    }
+
+    pub fn take_string(&mut self, chars: String) -> ObjRef {
+        let hash = object::hash_string(&chars);
+        if let Some(interned) = self.strings.find_string(&chars, hash) {
+            interned
+        } else {
+            self.intern(ObjString::new(chars.into_boxed_str()))
+        }
+    }
+
+    pub fn new_function(&mut self, function: ObjFunction) -> ObjRef {
+        self.allocate(ObjKind::Function(function))
+    }
+
+    pub fn new_native(&mut self, native: ObjNative) -> ObjRef {
+        self.allocate(ObjKind::Native(native))
+    }
+
+    pub fn new_closure(&mut self, closure: ObjClosure) -> ObjRef {
+        self.allocate(ObjKind::Closure(closure))
+    }
+
+    pub fn new_upvalue(&mut self, upvalue: ObjUpvalue) -> ObjRef {
+        self.allocate(ObjKind::Upvalue(upvalue))
+    }
+
+    pub fn new_class(&mut self, class: ObjClass) -> ObjRef {
+        self.allocate(ObjKind::Class(class))
+    }
+
+    pub fn new_instance(&mut self, instance: ObjInstance) -> ObjRef {
+        self.allocate(ObjKind::Instance(instance))
+    }
+
+    pub fn new_bound_method(&mut self, bound_method: ObjBoundMethod) -> ObjRef {
+        self.allocate(ObjKind::BoundMethod(bound_method))
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        if let Value::Obj(object) = value {
+            self.mark_object(object);
+        }
+    }
+
+    pub fn mark_object(&mut self, object: ObjRef) {
+        if object.is_marked() {
+            return;
+        }
+        object.set_marked(true);
+        self.gray_stack.push(object);
+    }
+
+    pub fn mark_table(&mut self, table: &Table) {
+        for (key, value) in table.iter() {
+            self.mark_object(key);
+            self.mark_value(value);
+        }
+    }
+
+    /// Marks everything a function refers to; also used by the compiler for
+    /// functions that are still being compiled and so are not on the heap.
+    pub fn mark_function(&mut self, function: &ObjFunction) {
+        if let Some(name) = function.name {
+            self.mark_object(name);
+        }
+        for index in 0..function.chunk.constants().count() {
+            self.mark_value(function.chunk.constants()[index]);
+        }
+    }
+
+    /// Frees every object not reachable from the roots marked since the last
+    /// collection.
+    pub fn collect_garbage(&mut self) {
+        self.trace_references();
+        self.strings.remove_white();
+        self.sweep();
+        self.next_gc = self.bytes_allocated * GC_HEAP_GROW_FACTOR;
+    }
+
+    fn trace_references(&mut self) {
+        while let Some(object) = self.gray_stack.pop() {
+            self.blacken_object(object);
+        }
+    }
+
+    fn blacken_object(&mut self, object: ObjRef) {
+        match object.kind() {
+            ObjKind::String(_) | ObjKind::Native(_) => {}
+            ObjKind::Function(function) => self.mark_function(function),
+            ObjKind::Closure(closure) => {
+                self.mark_object(closure.function);
+                for upvalue in closure.upvalues.iter() {
+                    self.mark_object(*upvalue);
+                }
+            }
+            ObjKind::Upvalue(upvalue) => {
+                if let UpvalueLocation::Closed(value) = upvalue.location.get() {
+                    self.mark_value(value);
+                }
+            }
+            ObjKind::Class(class) => {
+                self.mark_object(class.name);
+                self.mark_table(&class.methods.borrow());
+            }
+            ObjKind::Instance(instance) => {
+                self.mark_object(instance.class);
+                self.mark_table(&instance.fields.borrow());
+            }
+            ObjKind::BoundMethod(bound_method) => {
+                self.mark_value(bound_method.receiver);
+                self.mark_object(bound_method.method);
+            }
+        }
+    }
+
+    fn sweep(&mut self) {
+        let mut bytes_freed = 0;
+        self.objects.retain(|object| {
+            if object.is_marked() {
+                object.set_marked(false);
+                true
+            } else {
+                bytes_freed += Self::size_of(object);
+                Self::free(*object);
+                false
+            }
+        });
+        self.bytes_allocated -= bytes_freed;
+    }
+
+    fn intern(&mut self, string: ObjString) -> ObjRef {
+        let string = self.allocate(ObjKind::String(string));
+        self.strings.set(string, Value::Nil);
+        string
+    }
+
+    fn allocate(&mut self, kind: ObjKind) -> ObjRef {
+        let pointer = NonNull::from(Box::leak(Box::new(Obj::new(kind))));
+        let object = ObjRef::new(pointer);
+        self.bytes_allocated += Self::size_of(&object);
+        self.objects.push(object);
+        object
+    }
+
+    fn free(object: ObjRef) {
+        // SAFETY: every object was allocated by `allocate` and is freed exactly once.
+        drop(unsafe { Box::from_raw(object.as_ptr()) });
+    }
+
+    fn size_of(object: &Obj) -> usize {
+        let owned = match object.kind() {
+            ObjKind::String(string) => string.as_str().len(),
+            ObjKind::Function(function) => {
+                function.chunk.count()
+                    + function.chunk.constants().count() * mem::size_of::<Value>()
+            }
+            ObjKind::Closure(closure) => closure.upvalues.len() * mem::size_of::<ObjRef>(),
+            ObjKind::Native(_)
+            | ObjKind::Upvalue(_)
+            | ObjKind::Class(_)
+            | ObjKind::Instance(_)
+            | ObjKind::BoundMethod(_) => 0,
+        };
+        mem::size_of::<Obj>() + owned
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for object in self.objects.drain(..) {
+            Self::free(object);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_are_interned() {
+        let mut heap = Heap::new();
+        let a = heap.copy_string("lox");
+        let b = heap.take_string(String::from("lox"));
+        let c = heap.copy_string("clox");
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.as_string().unwrap().as_str(), "lox");
+        assert_eq!(heap.objects.len(), 2);
+    }
+
+    #[test]
+    fn collects_unreachable_objects() {
+        let mut heap = Heap::new();
+        let kept = heap.copy_string("kept");
+        heap.copy_string("garbage");
+        let mut function = ObjFunction::new(Some(kept));
+        let constant = heap.copy_string("constant");
+        function.chunk.add_constant(Value::from(constant));
+        let function = heap.new_function(function);
+        let bytes_allocated = heap.bytes_allocated();
+
+        heap.mark_object(function);
+        heap.collect_garbage();
+
+        assert_eq!(heap.objects, vec![kept, constant, function]);
+        assert!(heap.bytes_allocated() < bytes_allocated);
+        assert!(heap.objects.iter().all(|object| !object.is_marked()));
+    }
+
+    #[test]
+    fn intern_table_does_not_keep_strings_alive() {
+        let mut heap = Heap::new();
+        heap.copy_string("garbage");
+        heap.collect_garbage();
+
+        assert!(heap.objects.is_empty());
+        assert_eq!(heap.bytes_allocated(), 0);
+        let hash = object::hash_string("garbage");
+        assert_eq!(heap.strings.find_string("garbage", hash), None);
+    }
+
+    #[test]
+    fn closures_keep_closed_upvalues_alive() {
+        let mut heap = Heap::new();
+        let function = heap.new_function(ObjFunction::new(None));
+        let upvalue = heap.new_upvalue(ObjUpvalue::new(0));
+        let captured = heap.copy_string("captured");
+        upvalue
+            .as_upvalue()
+            .unwrap()
+            .location
+            .set(UpvalueLocation::Closed(Value::from(captured)));
+        let closure = heap.new_closure(ObjClosure::new(function, Box::new([upvalue])));
+
+        heap.mark_object(closure);
+        heap.collect_garbage();
+
+        assert_eq!(heap.objects, vec![function, upvalue, captured, closure]);
+    }
+}
@@ -1,4 +1,15 @@
 pub mod chunk;
+pub mod compiler;
 pub mod debug;
+pub mod machine;
+pub mod memory;
+pub mod native;
+pub mod object;
 pub mod run_length;
+pub mod scanner;
+pub mod serialize;
+pub mod table;
 pub mod value;
+pub mod verify;
+
+pub use machine::{InterpretResult, VM};
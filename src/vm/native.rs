@@ -0,0 +1,17 @@
+use std::{io::Write, time::SystemTime};
+
+use super::value::Value;
+
+pub type NativeFn = fn(&mut dyn Write, &[Value]) -> Result<Value, String>;
+
+pub fn clock(_output: &mut dyn Write, _arguments: &[Value]) -> Result<Value, String> {
+    let duration = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| String::from("SystemTime before UNIX EPOCH!"))?;
+    Ok(Value::from(duration.as_secs_f64()))
+}
+
+pub fn print(output: &mut dyn Write, arguments: &[Value]) -> Result<Value, String> {
+    writeln!(output, "{}", arguments[0]).map_err(|err| format!("Print error: {err}"))?;
+    Ok(Value::Nil)
+}
@@ -0,0 +1,389 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+    ops::Deref,
+    ptr::NonNull,
+};
+
+use super::{chunk::Chunk, native::NativeFn, table::Table, value::Value};
+
+#[derive(Debug)]
+pub struct Obj {
+    is_marked: Cell<bool>,
+    kind: ObjKind,
+}
+
+impl Obj {
+    pub fn new(kind: ObjKind) -> Self {
+        Self {
+            is_marked: Cell::new(false),
+            kind,
+        }
+    }
+
+    pub fn kind(&self) -> &ObjKind {
+        &self.kind
+    }
+
+    pub fn is_marked(&self) -> bool {
+        self.is_marked.get()
+    }
+
+    pub(super) fn set_marked(&self, is_marked: bool) {
+        self.is_marked.set(is_marked);
+    }
+
+    pub fn as_string(&self) -> Option<&ObjString> {
+        if let ObjKind::String(string) = &self.kind {
+            Some(string)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_function(&self) -> Option<&ObjFunction> {
+        if let ObjKind::Function(function) = &self.kind {
+            Some(function)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_native(&self) -> Option<&ObjNative> {
+        if let ObjKind::Native(native) = &self.kind {
+            Some(native)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_closure(&self) -> Option<&ObjClosure> {
+        if let ObjKind::Closure(closure) = &self.kind {
+            Some(closure)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_upvalue(&self) -> Option<&ObjUpvalue> {
+        if let ObjKind::Upvalue(upvalue) = &self.kind {
+            Some(upvalue)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_class(&self) -> Option<&ObjClass> {
+        if let ObjKind::Class(class) = &self.kind {
+            Some(class)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_instance(&self) -> Option<&ObjInstance> {
+        if let ObjKind::Instance(instance) = &self.kind {
+            Some(instance)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_bound_method(&self) -> Option<&ObjBoundMethod> {
+        if let ObjKind::BoundMethod(bound_method) = &self.kind {
+            Some(bound_method)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for Obj {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ObjKind::String(string) => write!(f, "{string}"),
+            ObjKind::Function(function) => write!(f, "{function}"),
+            ObjKind::Native(native) => write!(f, "{native}"),
+            ObjKind::Closure(closure) => write!(f, "{closure}"),
+            ObjKind::Upvalue(_) => write!(f, "upvalue"),
+            ObjKind::Class(class) => write!(f, "{class}"),
+            ObjKind::Instance(instance) => write!(f, "{instance}"),
+            ObjKind::BoundMethod(bound_method) => write!(f, "{bound_method}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ObjKind {
+    String(ObjString),
+    Function(ObjFunction),
+    Native(ObjNative),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+}
+
+#[derive(Debug)]
+pub struct ObjString {
+    chars: Box<str>,
+    hash: u32,
+}
+
+impl ObjString {
+    pub fn new(chars: Box<str>) -> Self {
+        let hash = hash_string(&chars);
+        Self { chars, hash }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.chars
+    }
+
+    pub fn hash(&self) -> u32 {
+        self.hash
+    }
+}
+
+impl Display for ObjString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.chars)
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjFunction {
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    pub name: Option<ObjRef>,
+    /// Whether this is the top-level code of a script rather than a lambda,
+    /// as neither has a name.
+    pub is_script: bool,
+}
+
+impl ObjFunction {
+    pub fn new(name: Option<ObjRef>) -> Self {
+        Self {
+            arity: 0,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+            name,
+            is_script: false,
+        }
+    }
+
+    pub fn script() -> Self {
+        Self {
+            is_script: true,
+            ..Self::new(None)
+        }
+    }
+}
+
+impl Display for ObjFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(name) = self.name {
+            write!(f, "<fn {name}>")
+        } else if self.is_script {
+            write!(f, "<script>")
+        } else {
+            write!(f, "<anonymous fn>")
+        }
+    }
+}
+
+pub struct ObjNative {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl ObjNative {
+    pub fn new(name: &'static str, arity: usize, function: NativeFn) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+        }
+    }
+}
+
+impl std::fmt::Debug for ObjNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ObjNative")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl Display for ObjNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjClosure {
+    pub function: ObjRef,
+    pub upvalues: Box<[ObjRef]>,
+}
+
+impl ObjClosure {
+    pub fn new(function: ObjRef, upvalues: Box<[ObjRef]>) -> Self {
+        Self { function, upvalues }
+    }
+
+    pub fn function(&self) -> &ObjFunction {
+        self.function
+            .as_function()
+            .expect("Closures wrap functions.")
+    }
+}
+
+impl Display for ObjClosure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+/// Where a captured variable currently lives: in a stack slot while the
+/// enclosing function is running, or inside the upvalue once it has returned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpvalueLocation {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct ObjUpvalue {
+    pub location: Cell<UpvalueLocation>,
+}
+
+impl ObjUpvalue {
+    pub fn new(slot: usize) -> Self {
+        Self {
+            location: Cell::new(UpvalueLocation::Open(slot)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjClass {
+    pub name: ObjRef,
+    pub methods: RefCell<Table>,
+}
+
+impl ObjClass {
+    pub fn new(name: ObjRef) -> Self {
+        Self {
+            name,
+            methods: RefCell::new(Table::new()),
+        }
+    }
+}
+
+impl Display for ObjClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjInstance {
+    pub class: ObjRef,
+    pub fields: RefCell<Table>,
+}
+
+impl ObjInstance {
+    pub fn new(class: ObjRef) -> Self {
+        Self {
+            class,
+            fields: RefCell::new(Table::new()),
+        }
+    }
+
+    pub fn class(&self) -> &ObjClass {
+        self.class.as_class().expect("Instances belong to classes.")
+    }
+}
+
+impl Display for ObjInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class().name)
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+impl ObjBoundMethod {
+    pub fn new(receiver: Value, method: ObjRef) -> Self {
+        Self { receiver, method }
+    }
+}
+
+impl Display for ObjBoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.method)
+    }
+}
+
+pub fn hash_string(chars: &str) -> u32 {
+    let mut hash: u32 = 2166136261;
+    for byte in chars.bytes() {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(16777619);
+    }
+    hash
+}
+
+/// A handle to an object owned by a [`Heap`](super::memory::Heap).
+///
+/// Handles compare by identity, which for interned strings is the same as
+/// comparing their contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjRef(NonNull<Obj>);
+
+impl ObjRef {
+    pub(super) fn new(pointer: NonNull<Obj>) -> Self {
+        Self(pointer)
+    }
+
+    pub(super) fn as_ptr(&self) -> *mut Obj {
+        self.0.as_ptr()
+    }
+}
+
+impl Deref for ObjRef {
+    type Target = Obj;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: handles are only created by the heap, which keeps every object
+        // alive for as long as it is reachable from the VM.
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl Display for ObjRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", **self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_fnv_1a() {
+        assert_eq!(hash_string(""), 2166136261);
+        assert_eq!(hash_string("a"), 0xe40c292c);
+        assert_eq!(ObjString::new("foobar".into()).hash(), 0xbf9cf968);
+    }
+}
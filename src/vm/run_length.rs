@@ -1,6 +1,10 @@
 use std::ops::Index;
 
-#[derive(Debug, Default)]
+/// A sequence stored as runs of equal elements.
+///
+/// Each run records the offset one past its last element, so lookups are a
+/// binary search over the runs.
+#[derive(Debug, Default, Clone)]
 pub struct RunLength<T> {
     entries: Vec<Entry<T>>,
 }
@@ -13,24 +17,50 @@ impl<T: PartialEq> RunLength<T> {
     }
 
     pub fn push(&mut self, element: T) {
+        let end = self.len() + 1;
         match self.entries.last_mut() {
             Some(entry) if entry.element == element => {
-                entry.count += 1;
+                entry.end = end;
             }
-            _ => self.entries.push(Entry::new(element, 1)),
+            _ => self.entries.push(Entry::new(element, end)),
         }
     }
 }
 
-#[derive(Debug)]
+impl<T> RunLength<T> {
+    pub fn len(&self) -> usize {
+        self.entries.last().map_or(0, |entry| entry.end)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let run = self.entries.partition_point(|entry| entry.end <= index);
+        self.entries.get(run).map(|entry| &entry.element)
+    }
+
+    /// Iterates over each element together with how many times in a row it
+    /// was pushed.
+    pub fn runs(&self) -> impl Iterator<Item = (&T, usize)> + '_ {
+        let starts = std::iter::once(0).chain(self.entries.iter().map(|entry| entry.end));
+        self.entries
+            .iter()
+            .zip(starts)
+            .map(|(entry, start)| (&entry.element, entry.end - start))
+    }
+}
+
+#[derive(Debug, Clone)]
 struct Entry<T> {
     element: T,
-    count: usize,
+    end: usize,
 }
 
 impl<T> Entry<T> {
-    fn new(element: T, count: usize) -> Self {
-        Self { element, count }
+    fn new(element: T, end: usize) -> Self {
+        Self { element, end }
     }
 }
 
@@ -38,19 +68,13 @@ impl<T> Index<usize> for RunLength<T> {
     type Output = T;
 
     fn index(&self, index: usize) -> &Self::Output {
-        let mut offset = index;
-        for entry in self.entries.iter() {
-            if offset < entry.count {
-                return &entry.element;
-            }
-            offset -= entry.count;
-        }
-        panic!("Index out of bounds.");
+        self.get(index).expect("Index out of bounds.")
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
 
     use super::*;
 
@@ -71,5 +95,54 @@ mod tests {
         assert_eq!(v[3], 'b');
         assert_eq!(v[4], 'c');
         assert_eq!(v[5], 'c');
+        assert_eq!(v.get(6), None);
+        assert_eq!(
+            v.runs().collect::<Vec<_>>(),
+            vec![(&'a', 1), (&'b', 3), (&'c', 2)]
+        );
+    }
+
+    #[test]
+    fn empty_has_no_elements() {
+        let v = RunLength::<usize>::new();
+        assert!(v.is_empty());
+        assert_eq!(v.len(), 0);
+        assert_eq!(v.get(0), None);
+        assert_eq!(v.runs().count(), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn matches_a_vec(elements in prop::collection::vec(0..4usize, 0..200), index in 0..250usize) {
+            let mut v = RunLength::new();
+            for element in elements.iter() {
+                v.push(*element);
+            }
+
+            prop_assert_eq!(v.len(), elements.len());
+            prop_assert_eq!(v.get(index), elements.get(index));
+            for (i, element) in elements.iter().enumerate() {
+                prop_assert_eq!(&v[i], element);
+            }
+        }
+
+        #[test]
+        fn runs_expand_to_the_elements(elements in prop::collection::vec(0..4usize, 0..200)) {
+            let mut v = RunLength::new();
+            for element in elements.iter() {
+                v.push(*element);
+            }
+
+            let expanded: Vec<_> = v
+                .runs()
+                .flat_map(|(element, count)| std::iter::repeat_n(*element, count))
+                .collect();
+            prop_assert_eq!(expanded, elements);
+            prop_assert!(v.runs().all(|(_, count)| count > 0));
+            prop_assert!(v
+                .runs()
+                .zip(v.runs().skip(1))
+                .all(|((a, _), (b, _))| a != b));
+        }
     }
 }
@@ -0,0 +1,352 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    Comma,
+    Dot,
+    Minus,
+    Plus,
+    Semicolon,
+    Colon,
+    Slash,
+    Star,
+
+    QuestionMark,
+    Bang,
+    BangEqual,
+    Equal,
+    EqualEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+
+    Identifier,
+    String,
+    Number,
+
+    And,
+    Class,
+    Else,
+    False,
+    Fun,
+    For,
+    If,
+    Nil,
+    Or,
+    Return,
+    Super,
+    This,
+    True,
+    Var,
+    While,
+
+    Error,
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub lexeme: &'a str,
+    pub line: usize,
+}
+
+impl<'a> Token<'a> {
+    pub fn new(kind: TokenKind, lexeme: &'a str, line: usize) -> Self {
+        Self { kind, lexeme, line }
+    }
+}
+
+pub struct Scanner<'a> {
+    source: &'a str,
+    start: usize,
+    current: usize,
+    line: usize,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            start: 0,
+            current: 0,
+            line: 1,
+        }
+    }
+
+    pub fn scan_token(&mut self) -> Token<'a> {
+        if let Some(error) = self.skip_whitespace() {
+            return error;
+        }
+        self.start = self.current;
+        if self.is_at_end() {
+            return self.make_token(TokenKind::Eof);
+        }
+
+        let ch = self.advance();
+        match ch {
+            '(' => self.make_token(TokenKind::LeftParen),
+            ')' => self.make_token(TokenKind::RightParen),
+            '{' => self.make_token(TokenKind::LeftBrace),
+            '}' => self.make_token(TokenKind::RightBrace),
+            ',' => self.make_token(TokenKind::Comma),
+            '.' => self.make_token(TokenKind::Dot),
+            '-' => self.make_token(TokenKind::Minus),
+            '+' => self.make_token(TokenKind::Plus),
+            ';' => self.make_token(TokenKind::Semicolon),
+            ':' => self.make_token(TokenKind::Colon),
+            '/' => self.make_token(TokenKind::Slash),
+            '*' => self.make_token(TokenKind::Star),
+            '?' => self.make_token(TokenKind::QuestionMark),
+            '!' => self.cond_make('=', TokenKind::BangEqual, TokenKind::Bang),
+            '=' => self.cond_make('=', TokenKind::EqualEqual, TokenKind::Equal),
+            '<' => self.cond_make('=', TokenKind::LessEqual, TokenKind::Less),
+            '>' => self.cond_make('=', TokenKind::GreaterEqual, TokenKind::Greater),
+            '"' => self.string(),
+            ch if ch.is_ascii_digit() => self.number(),
+            ch if ch.is_ascii_alphabetic() => self.identifier(),
+            _ => self.error_token("Unexpected character"),
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> Option<Token<'a>> {
+        loop {
+            match self.peek() {
+                ' ' | '\r' | '\t' => {
+                    self.advance();
+                }
+                '\n' => {
+                    self.line += 1;
+                    self.advance();
+                }
+                '/' if self.peek_next() == '/' => {
+                    while self.peek() != '\n' && !self.is_at_end() {
+                        self.advance();
+                    }
+                }
+                '/' if self.peek_next() == '*' => {
+                    self.advance();
+                    self.advance();
+                    if !self.block_comment() {
+                        return Some(self.error_token("Unexpected EOF"));
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    fn block_comment(&mut self) -> bool {
+        let mut nest = 1;
+        while !self.is_at_end() && nest > 0 {
+            let ch = self.advance();
+            if ch == '\n' {
+                self.line += 1;
+            }
+            if ch == '/' && self.match_char('*') {
+                nest += 1;
+            } else if ch == '*' && self.match_char('/') {
+                nest -= 1;
+            }
+        }
+        nest == 0
+    }
+
+    fn string(&mut self) -> Token<'a> {
+        while self.peek() != '"' && !self.is_at_end() {
+            if self.peek() == '\n' {
+                self.line += 1;
+            }
+            self.advance();
+        }
+        if self.is_at_end() {
+            self.error_token("Unterminated string")
+        } else {
+            self.advance();
+            self.make_token(TokenKind::String)
+        }
+    }
+
+    fn number(&mut self) -> Token<'a> {
+        while self.peek().is_ascii_digit() {
+            self.advance();
+        }
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
+            self.advance();
+        }
+        while self.peek().is_ascii_digit() {
+            self.advance();
+        }
+        self.make_token(TokenKind::Number)
+    }
+
+    fn identifier(&mut self) -> Token<'a> {
+        while self.peek().is_ascii_alphanumeric() {
+            self.advance();
+        }
+        self.make_token(Self::identifier_kind(self.current_lexeme()))
+    }
+
+    fn identifier_kind(text: &str) -> TokenKind {
+        match text {
+            "and" => TokenKind::And,
+            "class" => TokenKind::Class,
+            "else" => TokenKind::Else,
+            "false" => TokenKind::False,
+            "for" => TokenKind::For,
+            "fun" => TokenKind::Fun,
+            "if" => TokenKind::If,
+            "nil" => TokenKind::Nil,
+            "or" => TokenKind::Or,
+            "return" => TokenKind::Return,
+            "super" => TokenKind::Super,
+            "this" => TokenKind::This,
+            "true" => TokenKind::True,
+            "var" => TokenKind::Var,
+            "while" => TokenKind::While,
+            _ => TokenKind::Identifier,
+        }
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.current >= self.source.len()
+    }
+
+    fn advance(&mut self) -> char {
+        let ch = self.peek();
+        self.current += ch.len_utf8();
+        ch
+    }
+
+    fn match_char(&mut self, expected: char) -> bool {
+        if self.is_at_end() || self.peek() != expected {
+            false
+        } else {
+            self.advance();
+            true
+        }
+    }
+
+    fn peek(&self) -> char {
+        self.source[self.current..].chars().next().unwrap_or('\0')
+    }
+
+    fn peek_next(&self) -> char {
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
+    }
+
+    fn current_lexeme(&self) -> &'a str {
+        &self.source[self.start..self.current]
+    }
+
+    fn make_token(&self, kind: TokenKind) -> Token<'a> {
+        Token::new(kind, self.current_lexeme(), self.line)
+    }
+
+    fn cond_make(
+        &mut self,
+        if_match: char,
+        then_make: TokenKind,
+        else_make: TokenKind,
+    ) -> Token<'a> {
+        let kind = if self.match_char(if_match) {
+            then_make
+        } else {
+            else_make
+        };
+        self.make_token(kind)
+    }
+
+    fn error_token(&self, message: &'static str) -> Token<'a> {
+        Token::new(TokenKind::Error, message, self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_work() {
+        assert_eq!(
+            scan_kinds("!*+-/=<> <= == ?:"),
+            vec![
+                TokenKind::Bang,
+                TokenKind::Star,
+                TokenKind::Plus,
+                TokenKind::Minus,
+                TokenKind::Slash,
+                TokenKind::Equal,
+                TokenKind::Less,
+                TokenKind::Greater,
+                TokenKind::LessEqual,
+                TokenKind::EqualEqual,
+                TokenKind::QuestionMark,
+                TokenKind::Colon,
+                TokenKind::Eof,
+            ]
+        )
+    }
+
+    #[test]
+    fn literals_work() {
+        assert_eq!(
+            scan_tokens(r#"4.14 "+ -" and andaluzja"#),
+            vec![
+                Token::new(TokenKind::Number, "4.14", 1),
+                Token::new(TokenKind::String, "\"+ -\"", 1),
+                Token::new(TokenKind::And, "and", 1),
+                Token::new(TokenKind::Identifier, "andaluzja", 1),
+                Token::new(TokenKind::Eof, "", 1),
+            ]
+        )
+    }
+
+    #[test]
+    fn comments_and_lines_work() {
+        assert_eq!(
+            scan_tokens("a // comment\n/* /* x */\n y */ b"),
+            vec![
+                Token::new(TokenKind::Identifier, "a", 1),
+                Token::new(TokenKind::Identifier, "b", 3),
+                Token::new(TokenKind::Eof, "", 3),
+            ]
+        )
+    }
+
+    #[test]
+    fn errors_are_reported_as_tokens() {
+        assert_eq!(
+            scan_tokens("ł \"abc"),
+            vec![
+                Token::new(TokenKind::Error, "Unexpected character", 1),
+                Token::new(TokenKind::Error, "Unterminated string", 1),
+                Token::new(TokenKind::Eof, "", 1),
+            ]
+        )
+    }
+
+    fn scan_tokens(source: &str) -> Vec<Token<'_>> {
+        let mut scanner = Scanner::new(source);
+        let mut tokens = Vec::new();
+        loop {
+            let token = scanner.scan_token();
+            tokens.push(token);
+            if token.kind == TokenKind::Eof {
+                return tokens;
+            }
+        }
+    }
+
+    fn scan_kinds(source: &str) -> Vec<TokenKind> {
+        scan_tokens(source)
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+}
@@ -0,0 +1,483 @@
+//! The `.loxc` format for compiled scripts.
+//!
+//! A file starts with the magic bytes `LOXC` and a format version, followed by
+//! the script's chunk: its code, its line table as runs of `(line, count)` and
+//! its constants, each prefixed with a tag. Function constants carry their own
+//! chunks. Integers are big-endian `u32`s unless noted otherwise.
+
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, Read, Write},
+};
+
+use super::{
+    chunk::Chunk,
+    memory::Heap,
+    object::ObjFunction,
+    value::Value,
+    verify::{self, VerifyError},
+};
+
+const MAGIC: &[u8; 4] = b"LOXC";
+const VERSION: u16 = 1;
+
+/// How deeply functions may be nested in a script, which bounds the
+/// recursion of loading and verifying it.
+pub const MAX_FUNCTION_DEPTH: usize = 256;
+
+const TAG_NIL: u8 = 0;
+const TAG_BOOL: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+const TAG_STRING: u8 = 3;
+const TAG_FUNCTION: u8 = 4;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Truncated,
+    NotBytecode,
+    UnsupportedVersion(u16),
+    Malformed(String),
+    Invalid(VerifyError),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{err}"),
+            LoadError::Truncated => write!(f, "Unexpected end of bytecode."),
+            LoadError::NotBytecode => write!(f, "Not a compiled Lox file."),
+            LoadError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported bytecode version {version}.")
+            }
+            LoadError::Malformed(message) => write!(f, "{message}"),
+            LoadError::Invalid(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            LoadError::Truncated
+        } else {
+            LoadError::Io(err)
+        }
+    }
+}
+
+impl From<VerifyError> for LoadError {
+    fn from(err: VerifyError) -> Self {
+        LoadError::Invalid(err)
+    }
+}
+
+impl Chunk {
+    /// Writes the chunk as a `.loxc` file.
+    pub fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_be_bytes())?;
+        self.write_body(writer)
+    }
+
+    /// Reads and verifies a chunk written by [`Chunk::write_to`], allocating
+    /// its strings and functions on `heap`.
+    ///
+    /// Nothing roots the chunk's constants, so the caller must not collect
+    /// garbage until the chunk is reachable from the VM.
+    pub fn read_from(reader: &mut dyn Read, heap: &mut Heap) -> Result<Chunk, LoadError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(LoadError::NotBytecode);
+        }
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_be_bytes(version);
+        if version != VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+
+        let chunk = Self::read_body(reader, heap, 0)?;
+        if reader.read(&mut [0])? != 0 {
+            return Err(LoadError::Malformed(String::from(
+                "Unexpected data after the chunk.",
+            )));
+        }
+        verify::verify(&chunk)?;
+        Ok(chunk)
+    }
+
+    fn write_body(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_u32(writer, self.count())?;
+        writer.write_all(self.code())?;
+
+        write_u32(writer, self.lines().runs().count())?;
+        for (line, count) in self.lines().runs() {
+            write_u32(writer, *line)?;
+            write_u32(writer, count)?;
+        }
+
+        write_u32(writer, self.constants().count())?;
+        for index in 0..self.constants().count() {
+            write_value(writer, self.constants()[index])?;
+        }
+        Ok(())
+    }
+
+    /// Reads the chunk of a function nested `depth` functions deep in the
+    /// script.
+    fn read_body(reader: &mut dyn Read, heap: &mut Heap, depth: usize) -> Result<Chunk, LoadError> {
+        let code = read_bytes(reader)?;
+
+        let mut runs = Vec::new();
+        for _ in 0..read_u32(reader)? {
+            runs.push((read_u32(reader)?, read_u32(reader)?));
+        }
+        if runs.iter().map(|(_, count)| *count).sum::<usize>() != code.len() {
+            return Err(LoadError::Malformed(String::from(
+                "Line table does not match the code.",
+            )));
+        }
+
+        let mut chunk = Chunk::new();
+        let mut bytes = code.into_iter();
+        for (line, count) in runs {
+            for byte in bytes.by_ref().take(count) {
+                chunk.write(byte, line);
+            }
+        }
+        for _ in 0..read_u32(reader)? {
+            let value = read_value(reader, heap, depth)?;
+            chunk.add_constant(value);
+        }
+        Ok(chunk)
+    }
+}
+
+fn write_value(writer: &mut dyn Write, value: Value) -> io::Result<()> {
+    match value {
+        Value::Nil => writer.write_all(&[TAG_NIL]),
+        Value::Bool(value) => writer.write_all(&[TAG_BOOL, u8::from(value)]),
+        Value::Number(value) => {
+            writer.write_all(&[TAG_NUMBER])?;
+            writer.write_all(&value.to_be_bytes())
+        }
+        Value::Obj(object) => {
+            if let Some(string) = object.as_string() {
+                writer.write_all(&[TAG_STRING])?;
+                write_string(writer, string.as_str())
+            } else if let Some(function) = object.as_function() {
+                writer.write_all(&[TAG_FUNCTION])?;
+                write_function(writer, function)
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Can't serialize the constant {object}."),
+                ))
+            }
+        }
+    }
+}
+
+fn read_value(reader: &mut dyn Read, heap: &mut Heap, depth: usize) -> Result<Value, LoadError> {
+    match read_u8(reader)? {
+        TAG_NIL => Ok(Value::Nil),
+        TAG_BOOL => match read_u8(reader)? {
+            0 => Ok(Value::from(false)),
+            1 => Ok(Value::from(true)),
+            byte => Err(LoadError::Malformed(format!("Invalid boolean {byte}."))),
+        },
+        TAG_NUMBER => {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            Ok(Value::from(f64::from_be_bytes(bytes)))
+        }
+        TAG_STRING => {
+            let string = read_string(reader)?;
+            Ok(Value::from(heap.take_string(string)))
+        }
+        TAG_FUNCTION => {
+            let function = read_function(reader, heap, depth + 1)?;
+            Ok(Value::from(heap.new_function(function)))
+        }
+        tag => Err(LoadError::Malformed(format!("Unknown constant tag {tag}."))),
+    }
+}
+
+fn write_function(writer: &mut dyn Write, function: &ObjFunction) -> io::Result<()> {
+    if let Some(name) = function.name {
+        writer.write_all(&[1])?;
+        write_string(writer, &name.to_string())?;
+    } else {
+        writer.write_all(&[0])?;
+    }
+    write_u32(writer, function.arity)?;
+    write_u32(writer, function.upvalue_count)?;
+    function.chunk.write_body(writer)
+}
+
+fn read_function(
+    reader: &mut dyn Read,
+    heap: &mut Heap,
+    depth: usize,
+) -> Result<ObjFunction, LoadError> {
+    if depth > MAX_FUNCTION_DEPTH {
+        return Err(LoadError::Malformed(String::from(
+            "Functions are nested too deeply.",
+        )));
+    }
+    let name = match read_u8(reader)? {
+        0 => None,
+        1 => {
+            let name = read_string(reader)?;
+            Some(heap.take_string(name))
+        }
+        byte => {
+            return Err(LoadError::Malformed(format!(
+                "Invalid function name marker {byte}."
+            )))
+        }
+    };
+    let mut function = ObjFunction::new(name);
+    function.arity = read_u32(reader)?;
+    function.upvalue_count = read_u32(reader)?;
+    function.chunk = Chunk::read_body(reader, heap, depth)?;
+    Ok(function)
+}
+
+fn write_string(writer: &mut dyn Write, string: &str) -> io::Result<()> {
+    write_u32(writer, string.len())?;
+    writer.write_all(string.as_bytes())
+}
+
+fn read_string(reader: &mut dyn Read) -> Result<String, LoadError> {
+    String::from_utf8(read_bytes(reader)?)
+        .map_err(|_| LoadError::Malformed(String::from("String constant is not UTF-8.")))
+}
+
+fn write_u32(writer: &mut dyn Write, value: usize) -> io::Result<()> {
+    let value = u32::try_from(value).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{value} does not fit in the bytecode format."),
+        )
+    })?;
+    writer.write_all(&value.to_be_bytes())
+}
+
+fn read_u32(reader: &mut dyn Read) -> Result<usize, LoadError> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes) as usize)
+}
+
+fn read_u8(reader: &mut dyn Read) -> Result<u8, LoadError> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Reads a length-prefixed byte string without trusting the length for the
+/// allocation.
+fn read_bytes(reader: &mut dyn Read) -> Result<Vec<u8>, LoadError> {
+    let length = read_u32(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != length {
+        return Err(LoadError::Truncated);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::{
+        chunk::OpCode,
+        compiler,
+        machine::{InterpretResult, VM},
+    };
+
+    use super::*;
+
+    const SCRIPT: &str = r#"
+        var greeting = "hello";
+        fun greet(name) {
+            fun punctuate() { return "!"; }
+            return greeting + " " + name + punctuate();
+        }
+        print(greet("world") + (nil == false ? "" : "?") + 1.5);
+    "#;
+
+    #[test]
+    fn chunks_round_trip() {
+        let mut heap = Heap::new();
+        let script = compiler::compile(SCRIPT, &mut heap, &|_| {}).unwrap();
+        let chunk = &script.as_function().unwrap().chunk;
+        let bytes = serialize(chunk);
+
+        let mut other_heap = Heap::new();
+        let loaded = Chunk::read_from(&mut bytes.as_slice(), &mut other_heap).unwrap();
+        assert_eq!(loaded.code(), chunk.code());
+        assert_eq!(
+            loaded.lines().runs().collect::<Vec<_>>(),
+            chunk.lines().runs().collect::<Vec<_>>()
+        );
+        assert_eq!(serialize(&loaded), bytes);
+
+        let greet = loaded.constants()[3_usize].as_obj().unwrap();
+        let greet = greet.as_function().unwrap();
+        assert_eq!(greet.to_string(), "<fn greet>");
+        assert_eq!(greet.arity, 1);
+        let punctuate = greet.chunk.constants()[0_usize].as_obj().unwrap();
+        assert_eq!(punctuate.to_string(), "<fn punctuate>");
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let mut heap = Heap::new();
+        let script = compiler::compile(SCRIPT, &mut heap, &|_| {}).unwrap();
+        let bytes = serialize(&script.as_function().unwrap().chunk);
+
+        for length in 0..bytes.len() {
+            let result = Chunk::read_from(&mut &bytes[..length], &mut heap);
+            assert!(
+                matches!(result, Err(LoadError::Truncated)),
+                "Loaded {length} of {} bytes.",
+                bytes.len()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_deeply_nested_functions() {
+        let mut bytes = Vec::from(*MAGIC);
+        bytes.extend(VERSION.to_be_bytes());
+        for _ in 0..100_000 {
+            // An empty chunk whose only constant is a nameless function.
+            bytes.extend([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, TAG_FUNCTION, 0]);
+            bytes.extend([0; 8]);
+        }
+
+        let mut heap = Heap::new();
+        let result = Chunk::read_from(&mut bytes.as_slice(), &mut heap);
+        assert!(
+            matches!(&result, Err(LoadError::Malformed(message)) if message.contains("nested")),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let mut heap = Heap::new();
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::from(1.0), 1);
+        chunk.write(OpCode::Return, 1);
+        let bytes = serialize(&chunk);
+        let load = |bytes: &[u8], heap: &mut Heap| Chunk::read_from(&mut &bytes[..], heap);
+
+        assert!(matches!(
+            load(b"#!/usr/bin/env clox", &mut heap),
+            Err(LoadError::NotBytecode)
+        ));
+
+        let mut other_version = bytes.clone();
+        other_version[5] = 2;
+        assert!(matches!(
+            load(&other_version, &mut heap),
+            Err(LoadError::UnsupportedVersion(2))
+        ));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            load(&trailing, &mut heap),
+            Err(LoadError::Malformed(_))
+        ));
+
+        let mut unknown_tag = bytes.clone();
+        let tag = unknown_tag.len() - 9;
+        unknown_tag[tag] = 9;
+        assert!(matches!(
+            load(&unknown_tag, &mut heap),
+            Err(LoadError::Malformed(_))
+        ));
+
+        let mut unknown_opcode = bytes.clone();
+        unknown_opcode[12] = 0xff;
+        assert!(matches!(
+            load(&unknown_opcode, &mut heap),
+            Err(LoadError::Invalid(VerifyError::UnknownOpcode {
+                offset: 2,
+                opcode: 0xff
+            }))
+        ));
+
+        let mut bad_constant = bytes;
+        bad_constant[11] = 1;
+        assert!(matches!(
+            load(&bad_constant, &mut heap),
+            Err(LoadError::Invalid(VerifyError::ConstantOutOfRange {
+                offset: 0,
+                constant: 1
+            }))
+        ));
+    }
+
+    #[test]
+    fn verified_chunks_fail_with_runtime_errors() {
+        let nil_not = [OpCode::Nil as u8, OpCode::Not as u8];
+        // The subclass loaded for `INHERIT` is a boolean.
+        let inherit = patch(
+            "class B {} class A < B {}",
+            |code| code[2] == OpCode::Inherit as u8 && code[0] == OpCode::GetGlobal as u8,
+            |code| code[..2].copy_from_slice(&nil_not),
+        );
+        // The class loaded for `METHOD` is a boolean.
+        let method = patch(
+            "class A { f() {} }",
+            |code| code[0] == OpCode::GetGlobal as u8 && code[2] == OpCode::Closure as u8,
+            |code| code[..2].copy_from_slice(&nil_not),
+        );
+        // `init` is a bare function instead of a closure.
+        let init = patch(
+            "class A { init() {} } A();",
+            |code| code[0] == OpCode::Closure as u8 && code[2] == OpCode::Method as u8,
+            |code| code[0] = OpCode::Constant as u8,
+        );
+
+        for bytes in [inherit, method, init] {
+            let mut vm = VM::new_with_output(io::sink());
+            assert_eq!(
+                vm.interpret_compiled(&mut bytes.as_slice()).unwrap(),
+                InterpretResult::RuntimeError
+            );
+        }
+    }
+
+    /// Compiles `source` and rewrites the first three bytes of its code that
+    /// match `find`.
+    fn patch(source: &str, find: impl Fn(&[u8]) -> bool, replace: impl Fn(&mut [u8])) -> Vec<u8> {
+        let mut heap = Heap::new();
+        let script = compiler::compile(source, &mut heap, &|_| {}).unwrap();
+        let chunk = &script.as_function().unwrap().chunk;
+        let mut bytes = serialize(chunk);
+        // The code follows the magic bytes, the version and its length.
+        let code = &mut bytes[10..10 + chunk.code().len()];
+        let start = code
+            .windows(3)
+            .position(find)
+            .expect("Pattern is in the code.");
+        replace(&mut code[start..start + 3]);
+        bytes
+    }
+
+    fn serialize(chunk: &Chunk) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        chunk.write_to(&mut bytes).unwrap();
+        bytes
+    }
+}
@@ -0,0 +1,225 @@
+use super::{object::ObjRef, value::Value};
+
+const MAX_LOAD: f64 = 0.75;
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    key: Option<ObjRef>,
+    value: Value,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        key: None,
+        value: Value::Nil,
+    };
+
+    fn is_tombstone(&self) -> bool {
+        self.key.is_none() && self.value != Value::Nil
+    }
+}
+
+/// Open-addressing hash table keyed by interned strings.
+#[derive(Debug, Default, Clone)]
+pub struct Table {
+    count: usize,
+    entries: Vec<Entry>,
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self {
+            count: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, key: ObjRef) -> Option<Value> {
+        if self.count == 0 {
+            return None;
+        }
+        let entry = &self.entries[Self::find_entry(&self.entries, key)];
+        entry.key.map(|_| entry.value)
+    }
+
+    /// Inserts or overwrites `key`, returning `true` if the key is new.
+    pub fn set(&mut self, key: ObjRef, value: Value) -> bool {
+        if (self.count + 1) as f64 > self.capacity() as f64 * MAX_LOAD {
+            self.adjust_capacity(Self::grow_capacity(self.capacity()));
+        }
+
+        let index = Self::find_entry(&self.entries, key);
+        let entry = &mut self.entries[index];
+        let is_new_key = entry.key.is_none();
+        if is_new_key && !entry.is_tombstone() {
+            self.count += 1;
+        }
+        entry.key = Some(key);
+        entry.value = value;
+        is_new_key
+    }
+
+    pub fn delete(&mut self, key: ObjRef) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+        let index = Self::find_entry(&self.entries, key);
+        let entry = &mut self.entries[index];
+        if entry.key.is_none() {
+            return false;
+        }
+        entry.key = None;
+        entry.value = Value::from(true);
+        true
+    }
+
+    pub fn add_all(&mut self, from: &Table) {
+        for (key, value) in from.iter() {
+            self.set(key, value);
+        }
+    }
+
+    pub fn find_string(&self, chars: &str, hash: u32) -> Option<ObjRef> {
+        if self.count == 0 {
+            return None;
+        }
+        let mut index = hash as usize & (self.capacity() - 1);
+        loop {
+            let entry = &self.entries[index];
+            match entry.key {
+                None if !entry.is_tombstone() => return None,
+                Some(key) => {
+                    let string = key.as_string().expect("Table keys are strings.");
+                    if string.hash() == hash && string.as_str() == chars {
+                        return Some(key);
+                    }
+                }
+                None => {}
+            }
+            index = (index + 1) & (self.capacity() - 1);
+        }
+    }
+
+    /// Deletes every entry whose key was not marked by the collector.
+    pub fn remove_white(&mut self) {
+        for entry in self.entries.iter_mut() {
+            if entry.key.is_some_and(|key| !key.is_marked()) {
+                entry.key = None;
+                entry.value = Value::from(true);
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ObjRef, Value)> + '_ {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.key.map(|key| (key, entry.value)))
+    }
+
+    fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn grow_capacity(capacity: usize) -> usize {
+        if capacity < 8 {
+            8
+        } else {
+            capacity * 2
+        }
+    }
+
+    fn adjust_capacity(&mut self, capacity: usize) {
+        let mut entries = vec![Entry::EMPTY; capacity];
+        self.count = 0;
+        for entry in self.entries.iter() {
+            if let Some(key) = entry.key {
+                let index = Self::find_entry(&entries, key);
+                entries[index] = *entry;
+                self.count += 1;
+            }
+        }
+        self.entries = entries;
+    }
+
+    fn find_entry(entries: &[Entry], key: ObjRef) -> usize {
+        let capacity = entries.len();
+        let mut index = Self::hash_of(key) as usize & (capacity - 1);
+        let mut tombstone = None;
+        loop {
+            let entry = &entries[index];
+            match entry.key {
+                Some(entry_key) if entry_key == key => return index,
+                Some(_) => {}
+                None if entry.is_tombstone() => {
+                    tombstone.get_or_insert(index);
+                }
+                None => return tombstone.unwrap_or(index),
+            }
+            index = (index + 1) & (capacity - 1);
+        }
+    }
+
+    fn hash_of(key: ObjRef) -> u32 {
+        key.as_string().expect("Table keys are strings.").hash()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::memory::Heap;
+
+    use super::*;
+
+    #[test]
+    fn set_get_and_delete_work() {
+        let mut heap = Heap::new();
+        let a = heap.copy_string("a");
+        let b = heap.copy_string("b");
+        let mut table = Table::new();
+
+        assert_eq!(table.get(a), None);
+        assert!(table.set(a, Value::from(1.0)));
+        assert!(!table.set(a, Value::from(2.0)));
+        assert!(table.set(b, Value::Nil));
+        assert_eq!(table.get(a), Some(Value::from(2.0)));
+        assert_eq!(table.get(b), Some(Value::Nil));
+
+        assert!(table.delete(a));
+        assert!(!table.delete(a));
+        assert_eq!(table.get(a), None);
+        assert_eq!(table.get(b), Some(Value::Nil));
+    }
+
+    #[test]
+    fn grows_and_reuses_tombstones() {
+        let mut heap = Heap::new();
+        let keys: Vec<_> = (0..100)
+            .map(|i| heap.copy_string(&format!("key{i}")))
+            .collect();
+        let mut table = Table::new();
+        for (i, key) in keys.iter().enumerate() {
+            table.set(*key, Value::from(i as f64));
+        }
+        for key in keys.iter().step_by(2) {
+            table.delete(*key);
+        }
+        for (i, key) in keys.iter().enumerate() {
+            let expected = (i % 2 == 1).then_some(Value::from(i as f64));
+            assert_eq!(table.get(*key), expected);
+        }
+        assert!(table.set(keys[0], Value::Nil));
+        assert_eq!(table.iter().count(), 51);
+    }
+
+    #[test]
+    fn find_string_looks_up_by_contents() {
+        let mut heap = Heap::new();
+        let key = heap.copy_string("global");
+        let mut table = Table::new();
+        table.set(key, Value::Nil);
+
+        let hash = key.as_string().unwrap().hash();
+        assert_eq!(table.find_string("global", hash), Some(key));
+        assert_eq!(table.find_string("other", hash), None);
+    }
+}
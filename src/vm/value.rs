@@ -1,8 +1,85 @@
-use std::ops::Index;
+use std::{fmt::Display, ops::Index};
 
-pub type Value = f64;
+use super::object::{ObjRef, ObjString};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Obj(ObjRef),
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Bool(value) => *value,
+            _ => true,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        if let Value::Number(value) = self {
+            Some(*value)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_obj(&self) -> Option<ObjRef> {
+        if let Value::Obj(object) = self {
+            Some(*object)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&ObjString> {
+        if let Value::Obj(object) = self {
+            object.as_string()
+        } else {
+            None
+        }
+    }
+}
+
+impl From<()> for Value {
+    fn from(_value: ()) -> Self {
+        Value::Nil
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<ObjRef> for Value {
+    fn from(value: ObjRef) -> Self {
+        Value::Obj(value)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Number(value) => write!(f, "{value}"),
+            Value::Obj(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct ValueArray {
     values: Vec<Value>,
 }
@@ -31,3 +108,37 @@ where
         &self.values[usize::from(index)]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::memory::Heap;
+
+    use super::*;
+
+    #[test]
+    fn truthiness_matches_lox() {
+        assert!(!Value::Nil.is_truthy());
+        assert!(!Value::from(false).is_truthy());
+        assert!(Value::from(true).is_truthy());
+        assert!(Value::from(0.0).is_truthy());
+    }
+
+    #[test]
+    fn equality_requires_same_type() {
+        assert_eq!(Value::Nil, Value::from(()));
+        assert_eq!(Value::from(1.0), Value::from(1.0));
+        assert_ne!(Value::from(0.0), Value::from(false));
+        assert_ne!(Value::Nil, Value::from(false));
+    }
+
+    #[test]
+    fn interned_strings_are_equal() {
+        let mut heap = Heap::new();
+        let a = Value::from(heap.copy_string("abc"));
+        let b = Value::from(heap.take_string(String::from("abc")));
+        assert_eq!(a, b);
+        assert!(a.is_truthy());
+        assert_eq!(a.to_string(), "abc");
+        assert_eq!(a.as_string().unwrap().as_str(), "abc");
+    }
+}
@@ -0,0 +1,726 @@
+//! Checks bytecode that did not come straight from the compiler before the VM
+//! runs it.
+//!
+//! The VM trusts its bytecode: an unknown opcode, an operand past the end of
+//! the code or a jump into the middle of an instruction would make it index
+//! out of bounds. The verifier decodes every instruction of a chunk and of the
+//! functions it contains, then follows every path through the code to check
+//! that the value stack never underflows and has the same depth wherever two
+//! paths meet.
+
+use std::{error::Error, fmt::Display};
+
+use super::{
+    chunk::{Chunk, OpCode},
+    object::ObjRef,
+    serialize::MAX_FUNCTION_DEPTH,
+    value::Value,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    UnknownOpcode {
+        offset: usize,
+        opcode: u8,
+    },
+    TruncatedInstruction {
+        offset: usize,
+    },
+    ConstantOutOfRange {
+        offset: usize,
+        constant: usize,
+    },
+    WrongConstantType {
+        offset: usize,
+        constant: usize,
+        expected: &'static str,
+    },
+    InvalidOperand {
+        offset: usize,
+        operand: u8,
+    },
+    InvalidJumpTarget {
+        offset: usize,
+        target: isize,
+    },
+    LocalOutOfRange {
+        offset: usize,
+        slot: u8,
+    },
+    UpvalueOutOfRange {
+        offset: usize,
+        index: u8,
+    },
+    StackUnderflow {
+        offset: usize,
+    },
+    StackMismatch {
+        offset: usize,
+        expected: usize,
+        found: usize,
+    },
+    FallsOffEnd {
+        offset: usize,
+    },
+    LineTableMismatch {
+        lines: usize,
+        code: usize,
+    },
+    NestedTooDeeply {
+        offset: usize,
+    },
+    InFunction {
+        function: String,
+        error: Box<VerifyError>,
+    },
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::UnknownOpcode { offset, opcode } => {
+                write!(f, "Unknown opcode {opcode} at offset {offset}.")
+            }
+            VerifyError::TruncatedInstruction { offset } => {
+                write!(f, "Instruction at offset {offset} is truncated.")
+            }
+            VerifyError::ConstantOutOfRange { offset, constant } => {
+                write!(f, "Constant {constant} at offset {offset} is out of range.")
+            }
+            VerifyError::WrongConstantType {
+                offset,
+                constant,
+                expected,
+            } => write!(
+                f,
+                "Constant {constant} at offset {offset} is not a {expected}."
+            ),
+            VerifyError::InvalidOperand { offset, operand } => {
+                write!(f, "Invalid operand {operand} at offset {offset}.")
+            }
+            VerifyError::InvalidJumpTarget { offset, target } => write!(
+                f,
+                "Jump at offset {offset} to {target} does not land on an instruction."
+            ),
+            VerifyError::LocalOutOfRange { offset, slot } => {
+                write!(f, "Local slot {slot} at offset {offset} is out of range.")
+            }
+            VerifyError::UpvalueOutOfRange { offset, index } => {
+                write!(f, "Upvalue {index} at offset {offset} is out of range.")
+            }
+            VerifyError::StackUnderflow { offset } => {
+                write!(f, "Instruction at offset {offset} underflows the stack.")
+            }
+            VerifyError::StackMismatch {
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Stack depth at offset {offset} is {found} on one path and {expected} on another."
+            ),
+            VerifyError::FallsOffEnd { offset } => {
+                write!(
+                    f,
+                    "Execution runs past the end of the code at offset {offset}."
+                )
+            }
+            VerifyError::LineTableMismatch { lines, code } => write!(
+                f,
+                "Line table covers {lines} bytes but the code has {code}."
+            ),
+            VerifyError::NestedTooDeeply { offset } => write!(
+                f,
+                "Function at offset {offset} is nested more than {MAX_FUNCTION_DEPTH} deep."
+            ),
+            VerifyError::InFunction { function, error } => write!(f, "In {function}: {error}"),
+        }
+    }
+}
+
+impl Error for VerifyError {}
+
+/// Checks a script's chunk and every function it contains.
+pub fn verify(chunk: &Chunk) -> Result<(), VerifyError> {
+    Verifier {
+        chunk,
+        arity: 0,
+        upvalue_count: 0,
+        depth: 0,
+    }
+    .verify()
+}
+
+struct Verifier<'a> {
+    chunk: &'a Chunk,
+    arity: usize,
+    upvalue_count: usize,
+    /// How many functions the chunk is nested in.
+    depth: usize,
+}
+
+impl Verifier<'_> {
+    fn verify(&self) -> Result<(), VerifyError> {
+        let lines = self.chunk.lines().len();
+        if lines != self.chunk.count() {
+            return Err(VerifyError::LineTableMismatch {
+                lines,
+                code: self.chunk.count(),
+            });
+        }
+        let instructions = self.decode()?;
+        self.check_stack(&instructions)
+    }
+
+    /// Splits the code into instructions, checking their operands and the
+    /// functions they refer to.
+    fn decode(&self) -> Result<Vec<Instruction<'_>>, VerifyError> {
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while offset < self.chunk.count() {
+            let instruction = Instruction::decode(self.chunk, offset)?;
+            if let Some(function) = instruction.function()? {
+                self.verify_function(offset, function)?;
+            }
+            offset += instruction.length;
+            instructions.push(instruction);
+        }
+
+        for instruction in instructions.iter() {
+            if let Some(target) = instruction.jump_target()? {
+                let lands_on_instruction = instructions
+                    .binary_search_by_key(&target, |other| other.offset)
+                    .is_ok();
+                if !lands_on_instruction {
+                    return Err(VerifyError::InvalidJumpTarget {
+                        offset: instruction.offset,
+                        target: target as isize,
+                    });
+                }
+            }
+        }
+        Ok(instructions)
+    }
+
+    fn verify_function(&self, offset: usize, function: ObjRef) -> Result<(), VerifyError> {
+        if self.depth == MAX_FUNCTION_DEPTH {
+            return Err(VerifyError::NestedTooDeeply { offset });
+        }
+        let function = function.as_function().expect("Checked to be a function.");
+        Verifier {
+            chunk: &function.chunk,
+            arity: function.arity,
+            upvalue_count: function.upvalue_count,
+            depth: self.depth + 1,
+        }
+        .verify()
+        .map_err(|error| VerifyError::InFunction {
+            function: function.to_string(),
+            error: Box::new(error),
+        })
+    }
+
+    /// Follows every path through the code, tracking the depth of the stack
+    /// relative to the frame's first slot, which holds the callee.
+    fn check_stack(&self, instructions: &[Instruction]) -> Result<(), VerifyError> {
+        let Some(first) = instructions.first() else {
+            return Err(VerifyError::FallsOffEnd { offset: 0 });
+        };
+        let index_of = |offset: usize| {
+            instructions
+                .binary_search_by_key(&offset, |instruction| instruction.offset)
+                .expect("Jump targets were checked.")
+        };
+
+        let mut depths: Vec<Option<usize>> = vec![None; instructions.len()];
+        let mut pending = vec![(first.offset, 1 + self.arity)];
+        while let Some((offset, depth)) = pending.pop() {
+            let index = index_of(offset);
+            match depths[index] {
+                Some(expected) if expected == depth => continue,
+                Some(expected) => {
+                    return Err(VerifyError::StackMismatch {
+                        offset,
+                        expected,
+                        found: depth,
+                    })
+                }
+                None => depths[index] = Some(depth),
+            }
+
+            let instruction = &instructions[index];
+            self.check_operands(instruction, depth)?;
+            let (needs, effect) = instruction.stack_effect()?;
+            if depth < needs {
+                return Err(VerifyError::StackUnderflow { offset });
+            }
+            let depth = depth.checked_add_signed(effect).expect("Checked above.");
+
+            let next = offset + instruction.length;
+            let falls_through = !matches!(
+                instruction.op_code,
+                OpCode::Return | OpCode::Jump | OpCode::Loop
+            );
+            if falls_through {
+                if next >= self.chunk.count() {
+                    return Err(VerifyError::FallsOffEnd { offset });
+                }
+                pending.push((next, depth));
+            }
+            if let Some(target) = instruction.jump_target()? {
+                pending.push((target, depth));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the operands that refer to local slots and upvalues, which are
+    /// only valid for some stack depths and enclosing functions.
+    fn check_operands(&self, instruction: &Instruction, depth: usize) -> Result<(), VerifyError> {
+        let offset = instruction.offset;
+        // A closure may capture the slot it is about to be stored in, so that
+        // a local function can refer to itself.
+        let slots = if instruction.function()?.is_some() {
+            depth + 1
+        } else {
+            depth
+        };
+        let check_slot = |slot: u8| {
+            if usize::from(slot) < slots {
+                Ok(())
+            } else {
+                Err(VerifyError::LocalOutOfRange { offset, slot })
+            }
+        };
+        let check_upvalue = |index: u8| {
+            if usize::from(index) < self.upvalue_count {
+                Ok(())
+            } else {
+                Err(VerifyError::UpvalueOutOfRange { offset, index })
+            }
+        };
+
+        match instruction.op_code {
+            OpCode::GetLocal | OpCode::SetLocal => check_slot(instruction.byte(1)?),
+            OpCode::GetUpvalue | OpCode::SetUpvalue => check_upvalue(instruction.byte(1)?),
+            OpCode::Closure | OpCode::ClosureLong => {
+                let (_, captures) = instruction.closure_operand()?;
+                for capture in (captures..instruction.length).step_by(2) {
+                    let index = instruction.byte(capture + 1)?;
+                    match instruction.byte(capture)? {
+                        0 => check_upvalue(index)?,
+                        1 => check_slot(index)?,
+                        operand => return Err(VerifyError::InvalidOperand { offset, operand }),
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Instruction<'a> {
+    chunk: &'a Chunk,
+    offset: usize,
+    op_code: OpCode,
+    length: usize,
+}
+
+impl<'a> Instruction<'a> {
+    /// Decodes the instruction at `offset`, checking that its operands are
+    /// inside the code and that its constants exist and have the right type.
+    fn decode(chunk: &'a Chunk, offset: usize) -> Result<Self, VerifyError> {
+        let op_code = OpCode::try_from(chunk.code()[offset])
+            .map_err(|opcode| VerifyError::UnknownOpcode { offset, opcode })?;
+        let mut instruction = Self {
+            chunk,
+            offset,
+            op_code,
+            length: 1,
+        };
+        instruction.length = match op_code {
+            OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Pop
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::CloseUpvalue
+            | OpCode::Return
+            | OpCode::Inherit => 1,
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call => {
+                instruction.byte(1)?;
+                2
+            }
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                instruction.byte(2)?;
+                3
+            }
+            OpCode::Constant => {
+                instruction.constant(usize::from(instruction.byte(1)?))?;
+                2
+            }
+            OpCode::ConstantLong => {
+                instruction.constant(instruction.long_operand()?)?;
+                4
+            }
+            OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Class
+            | OpCode::Method => {
+                instruction.string(usize::from(instruction.byte(1)?))?;
+                2
+            }
+            OpCode::DefineGlobalLong
+            | OpCode::GetGlobalLong
+            | OpCode::SetGlobalLong
+            | OpCode::GetPropertyLong
+            | OpCode::SetPropertyLong
+            | OpCode::GetSuperLong
+            | OpCode::ClassLong
+            | OpCode::MethodLong => {
+                instruction.string(instruction.long_operand()?)?;
+                4
+            }
+            OpCode::Invoke | OpCode::SuperInvoke => {
+                instruction.string(usize::from(instruction.byte(1)?))?;
+                instruction.byte(2)?;
+                3
+            }
+            OpCode::InvokeLong | OpCode::SuperInvokeLong => {
+                instruction.string(instruction.long_operand()?)?;
+                instruction.byte(4)?;
+                5
+            }
+            OpCode::Closure | OpCode::ClosureLong => {
+                let (constant, captures) = instruction.closure_operand()?;
+                let upvalue_count = instruction
+                    .function_constant(constant)?
+                    .as_function()
+                    .expect("Checked to be a function.")
+                    .upvalue_count;
+                let length = captures + 2 * upvalue_count;
+                instruction.byte(length - 1)?;
+                length
+            }
+        };
+        Ok(instruction)
+    }
+
+    /// The function wrapped by a closure instruction.
+    fn function(&self) -> Result<Option<ObjRef>, VerifyError> {
+        if matches!(self.op_code, OpCode::Closure | OpCode::ClosureLong) {
+            let (constant, _) = self.closure_operand()?;
+            self.function_constant(constant).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The function constant of a closure instruction and the offset of its
+    /// first captured variable.
+    fn closure_operand(&self) -> Result<(usize, usize), VerifyError> {
+        if self.op_code == OpCode::ClosureLong {
+            Ok((self.long_operand()?, 4))
+        } else {
+            Ok((usize::from(self.byte(1)?), 2))
+        }
+    }
+
+    fn jump_target(&self) -> Result<Option<usize>, VerifyError> {
+        let sign = match self.op_code {
+            OpCode::Jump | OpCode::JumpIfFalse => 1,
+            OpCode::Loop => -1,
+            _ => return Ok(None),
+        };
+        let jump = u16::from_be_bytes([self.byte(1)?, self.byte(2)?]);
+        let target = (self.offset + 3) as isize + sign * jump as isize;
+        usize::try_from(target)
+            .map(Some)
+            .map_err(|_| VerifyError::InvalidJumpTarget {
+                offset: self.offset,
+                target,
+            })
+    }
+
+    /// How many values the instruction needs on the stack and by how much it
+    /// changes the stack's depth.
+    fn stack_effect(&self) -> Result<(usize, isize), VerifyError> {
+        Ok(match self.op_code {
+            OpCode::Constant
+            | OpCode::ConstantLong
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetLocal
+            | OpCode::GetGlobal
+            | OpCode::GetGlobalLong
+            | OpCode::GetUpvalue
+            | OpCode::Closure
+            | OpCode::ClosureLong
+            | OpCode::Class
+            | OpCode::ClassLong => (0, 1),
+            OpCode::Jump | OpCode::Loop => (0, 0),
+            OpCode::SetLocal
+            | OpCode::SetGlobal
+            | OpCode::SetGlobalLong
+            | OpCode::SetUpvalue
+            | OpCode::GetProperty
+            | OpCode::GetPropertyLong
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::JumpIfFalse
+            | OpCode::Return => (1, 0),
+            OpCode::Pop
+            | OpCode::DefineGlobal
+            | OpCode::DefineGlobalLong
+            | OpCode::CloseUpvalue => (1, -1),
+            OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::SetProperty
+            | OpCode::SetPropertyLong
+            | OpCode::GetSuper
+            | OpCode::GetSuperLong
+            | OpCode::Inherit
+            | OpCode::Method
+            | OpCode::MethodLong => (2, -1),
+            OpCode::Call | OpCode::Invoke | OpCode::InvokeLong => {
+                let arg_count = usize::from(self.byte(self.length - 1)?);
+                (arg_count + 1, -(arg_count as isize))
+            }
+            OpCode::SuperInvoke | OpCode::SuperInvokeLong => {
+                let arg_count = usize::from(self.byte(self.length - 1)?);
+                (arg_count + 2, -(arg_count as isize) - 1)
+            }
+        })
+    }
+
+    fn byte(&self, index: usize) -> Result<u8, VerifyError> {
+        self.chunk.code().get(self.offset + index).copied().ok_or(
+            VerifyError::TruncatedInstruction {
+                offset: self.offset,
+            },
+        )
+    }
+
+    fn long_operand(&self) -> Result<usize, VerifyError> {
+        let [high, middle, low] = [self.byte(1)?, self.byte(2)?, self.byte(3)?];
+        Ok(u32::from_be_bytes([0, high, middle, low]) as usize)
+    }
+
+    fn constant(&self, constant: usize) -> Result<Value, VerifyError> {
+        if constant < self.chunk.constants().count() {
+            Ok(self.chunk.constants()[constant])
+        } else {
+            Err(VerifyError::ConstantOutOfRange {
+                offset: self.offset,
+                constant,
+            })
+        }
+    }
+
+    fn string(&self, constant: usize) -> Result<(), VerifyError> {
+        if self.constant(constant)?.as_string().is_some() {
+            Ok(())
+        } else {
+            Err(self.wrong_type(constant, "string"))
+        }
+    }
+
+    fn function_constant(&self, constant: usize) -> Result<ObjRef, VerifyError> {
+        match self.constant(constant)? {
+            Value::Obj(object) if object.as_function().is_some() => Ok(object),
+            _ => Err(self.wrong_type(constant, "function")),
+        }
+    }
+
+    fn wrong_type(&self, constant: usize, expected: &'static str) -> VerifyError {
+        VerifyError::WrongConstantType {
+            offset: self.offset,
+            constant,
+            expected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::{compiler, memory::Heap, object::ObjFunction};
+
+    use super::*;
+
+    #[test]
+    fn accepts_compiled_scripts() {
+        let mut heap = Heap::new();
+        let script = compiler::compile(
+            r#"
+            class A { init(x) { this.x = x; } get() { return this.x; } }
+            class B < A { get() { return super.get() + 1; } }
+            fun counter() {
+                var i = 0;
+                fun recurse(n) { return n > 0 ? recurse(n - 1) : i; }
+                return fun () { i = i + 1; return i; };
+            }
+            var c = counter();
+            for (var i = 0; i < 3; i = i + 1) {
+                if (i > 1 and c() or false) print(i > 2 ? B(i).get() : nil);
+            }
+            while (false) {}
+            "#,
+            &mut heap,
+            &|_| {},
+        )
+        .unwrap();
+        assert_eq!(verify(&script.as_function().unwrap().chunk), Ok(()));
+    }
+
+    #[test]
+    fn rejects_jumps_into_instructions() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Jump, 1);
+        chunk.write(0, 1);
+        chunk.write(1, 1);
+        chunk.write_constant(Value::Nil, 1);
+        chunk.write(OpCode::Return, 1);
+        assert_eq!(
+            verify(&chunk),
+            Err(VerifyError::InvalidJumpTarget {
+                offset: 0,
+                target: 4
+            })
+        );
+
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Loop, 1);
+        chunk.write(0, 1);
+        chunk.write(4, 1);
+        assert_eq!(
+            verify(&chunk),
+            Err(VerifyError::InvalidJumpTarget {
+                offset: 0,
+                target: -1
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_inconsistent_stacks() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Pop, 1);
+        chunk.write(OpCode::Pop, 1);
+        chunk.write(OpCode::Return, 1);
+        assert_eq!(
+            verify(&chunk),
+            Err(VerifyError::StackUnderflow { offset: 1 })
+        );
+
+        // Pushes a value on only one side of a branch.
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::True, 1);
+        chunk.write(OpCode::JumpIfFalse, 1);
+        chunk.write(0, 1);
+        chunk.write(1, 1);
+        chunk.write(OpCode::Nil, 1);
+        chunk.write(OpCode::Return, 1);
+        assert_eq!(
+            verify(&chunk),
+            Err(VerifyError::StackMismatch {
+                offset: 5,
+                expected: 2,
+                found: 3
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_code_that_runs_off_the_end() {
+        let mut chunk = Chunk::new();
+        assert_eq!(verify(&chunk), Err(VerifyError::FallsOffEnd { offset: 0 }));
+        chunk.write(OpCode::Nil, 1);
+        assert_eq!(verify(&chunk), Err(VerifyError::FallsOffEnd { offset: 0 }));
+    }
+
+    #[test]
+    fn checks_nested_functions() {
+        let mut heap = Heap::new();
+        let mut function = ObjFunction::new(Some(heap.copy_string("f")));
+        function.chunk.write(OpCode::GetUpvalue, 1);
+        function.chunk.write(0, 1);
+        function.chunk.write(OpCode::Return, 1);
+        let function = heap.new_function(function);
+
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(Value::from(function));
+        chunk.write(OpCode::Closure, 1);
+        chunk.write(constant as u8, 1);
+        chunk.write(OpCode::Return, 1);
+        assert_eq!(
+            verify(&chunk),
+            Err(VerifyError::InFunction {
+                function: String::from("<fn f>"),
+                error: Box::new(VerifyError::UpvalueOutOfRange {
+                    offset: 0,
+                    index: 0
+                })
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_deeply_nested_functions() {
+        let mut heap = Heap::new();
+        assert_eq!(
+            verify(&nested_functions(MAX_FUNCTION_DEPTH, &mut heap)),
+            Ok(())
+        );
+
+        let mut error = verify(&nested_functions(MAX_FUNCTION_DEPTH + 1, &mut heap)).unwrap_err();
+        let mut depth = 0;
+        while let VerifyError::InFunction { error: inner, .. } = error {
+            error = *inner;
+            depth += 1;
+        }
+        assert_eq!(depth, MAX_FUNCTION_DEPTH);
+        assert_eq!(error, VerifyError::NestedTooDeeply { offset: 0 });
+    }
+
+    /// A script whose chunk holds a closure of a function, which holds a
+    /// closure of another, `depth` times over.
+    fn nested_functions(depth: usize, heap: &mut Heap) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Nil, 1);
+        chunk.write(OpCode::Return, 1);
+        for _ in 0..depth {
+            let mut function = ObjFunction::new(None);
+            function.chunk = chunk;
+            let function = heap.new_function(function);
+            chunk = Chunk::new();
+            chunk.add_constant(Value::from(function));
+            chunk.write(OpCode::Closure, 1);
+            chunk.write(0, 1);
+            chunk.write(OpCode::Return, 1);
+        }
+        chunk
+    }
+}
@@ -20,6 +20,9 @@ pub trait ExecutionContext {
 pub trait Callable: Debug {
     fn arity(&self) -> usize;
 
+    /// How calls to this show up in stack traces, e.g. `f()`.
+    fn name(&self) -> String;
+
     fn call(
         &self,
         context: &mut dyn ExecutionContext,
@@ -27,7 +30,7 @@ pub trait Callable: Debug {
     ) -> Result<Cell, RuntimeError>;
 }
 
-#[allow(clippy::vtable_address_comparisons)]
+#[allow(ambiguous_wide_pointer_comparisons)]
 pub fn ptr_eq(left: &dyn Callable, right: &dyn Callable) -> bool {
     ptr::eq(left, right)
 }
@@ -53,6 +53,10 @@ impl Callable for Class {
         }
     }
 
+    fn name(&self) -> String {
+        format!("{}()", self.name)
+    }
+
     fn call(
         &self,
         context: &mut dyn ExecutionContext,
@@ -92,6 +96,20 @@ impl Instance {
         })
     }
 
+    /// Creates an instance with its fields already set.
+    pub fn with_fields(
+        class: Rc<Class>,
+        fields: impl IntoIterator<Item = (Rc<str>, Cell)>,
+    ) -> Rc<RefCell<Self>> {
+        let instance = Self::new(class);
+        instance.borrow_mut().fields.extend(fields);
+        instance
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Cell> {
+        self.fields.get(name)
+    }
+
     pub fn get(&self, name: &Token) -> Result<Cell, RuntimeError> {
         if let Some(value) = self.fields.get(name.lexeme()) {
             Ok(value.to_owned())
@@ -6,6 +6,8 @@ use super::{error::RuntimeError, value::Cell};
 pub enum ControlFlow {
     RuntimeError(RuntimeError),
     Return(Cell),
+    Break,
+    Continue,
 }
 
 impl From<RuntimeError> for ControlFlow {
@@ -25,6 +27,8 @@ impl Display for ControlFlow {
         match self {
             ControlFlow::RuntimeError(runtime_error) => write!(f, "{runtime_error}"),
             ControlFlow::Return(value) => write!(f, "{value}"),
+            ControlFlow::Break => write!(f, "break"),
+            ControlFlow::Continue => write!(f, "continue"),
         }
     }
 }
@@ -0,0 +1,640 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+};
+
+use super::{exit_code, token::Span};
+
+/// An error found while scanning, parsing, resolving or running a script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub span: Option<Span>,
+    pub message: String,
+    /// What the error was found at, like `'x'` or `end`.
+    pub near: Option<String>,
+    pub hints: Hints,
+    /// The calls a runtime error unwound through, innermost first.
+    pub trace: Vec<TraceFrame>,
+}
+
+impl Diagnostic {
+    pub fn error(code: Code, span: Option<Span>, message: &str) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            span,
+            message: String::from(message),
+            near: None,
+            hints: Hints::new(),
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn near(mut self, near: &str) -> Self {
+        self.near = Some(String::from(near));
+        self
+    }
+
+    pub fn with_hints(mut self, hints: Hints) -> Self {
+        self.hints = hints;
+        self
+    }
+
+    pub fn with_trace(mut self, trace: Vec<TraceFrame>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.code.phase()
+    }
+
+    /// Writes the diagnostic as a JSON object on a single line.
+    pub fn write_json(&self, out: &mut dyn Write, file: &str) -> io::Result<()> {
+        let position = |span: Option<Span>, field: fn(&Span) -> usize| {
+            span.as_ref()
+                .map_or(String::from("null"), |span| field(span).to_string())
+        };
+        let notes: Vec<_> = self
+            .hints
+            .notes
+            .iter()
+            .map(|note| json_string(note))
+            .collect();
+        let help = self
+            .hints
+            .help
+            .as_deref()
+            .map_or(String::from("null"), json_string);
+        let stack: Vec<_> = self
+            .trace
+            .iter()
+            .map(|frame| {
+                format!(
+                    "{{\"function\":{},\"line\":{},\"column\":{}}}",
+                    json_string(&frame.function),
+                    position(frame.span, |span| span.line),
+                    position(frame.span, |span| span.column),
+                )
+            })
+            .collect();
+        writeln!(
+            out,
+            "{{\"phase\":\"{}\",\"severity\":\"{}\",\"code\":\"{}\",\"file\":{},\"line\":{},\"column\":{},\"length\":{},\"message\":{},\"notes\":[{}],\"help\":{},\"stack\":[{}],\"exit_code\":{}}}",
+            self.phase(),
+            self.severity,
+            self.code,
+            json_string(file),
+            position(self.span, |span| span.line),
+            position(self.span, |span| span.column),
+            position(self.span, |span| span.length),
+            json_string(&self.message),
+            notes.join(","),
+            help,
+            stack.join(","),
+            self.phase().exit_code(),
+        )
+    }
+}
+
+/// A function that was being run when a runtime error happened, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    /// Like `f()`, `<anonymous fn>` or `script`.
+    pub function: String,
+    pub span: Option<Span>,
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            ch if ch.is_control() => json.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Formats the diagnostic on a line of its own, as in the book: runtime
+/// errors are followed by their line, the others are prefixed by it.
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.phase(), self.span) {
+            (Phase::Runtime, Some(span)) => write!(f, "{}\n[line {}]", self.message, span.line),
+            (Phase::Runtime, None) => write!(f, "Runtime error: {}", self.message),
+            (_, span) => {
+                if let Some(span) = span {
+                    write!(f, "[line {}] ", span.line)?;
+                }
+                write!(f, "{}", self.severity.title())?;
+                if let Some(near) = &self.near {
+                    write!(f, " at {near}")?;
+                }
+                write!(f, ": {}", self.message)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn title(self) -> &'static str {
+        match self {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// The stage of running a script that found a diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Scan,
+    Parse,
+    Resolve,
+    Runtime,
+}
+
+impl Phase {
+    /// The exit code jlox ends with when a diagnostic of this phase is
+    /// reported.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Phase::Scan | Phase::Parse | Phase::Resolve => exit_code::DATA_ERR,
+            Phase::Runtime => exit_code::SOFTWARE,
+        }
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Scan => write!(f, "scan"),
+            Phase::Parse => write!(f, "parse"),
+            Phase::Resolve => write!(f, "resolve"),
+            Phase::Runtime => write!(f, "runtime"),
+        }
+    }
+}
+
+/// Identifies the kind of a diagnostic independently of its message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    UnexpectedCharacter,
+    UnterminatedString,
+    UnterminatedComment,
+    Syntax,
+    DuplicateVariable,
+    ReadInOwnInitializer,
+    TopLevelReturn,
+    InitializerReturnsValue,
+    InheritsFromItself,
+    ThisOutsideClass,
+    LoopControlOutsideLoop,
+    Runtime,
+}
+
+impl Code {
+    pub fn phase(self) -> Phase {
+        match self {
+            Code::UnexpectedCharacter | Code::UnterminatedString | Code::UnterminatedComment => {
+                Phase::Scan
+            }
+            Code::Syntax => Phase::Parse,
+            Code::DuplicateVariable
+            | Code::ReadInOwnInitializer
+            | Code::TopLevelReturn
+            | Code::InitializerReturnsValue
+            | Code::InheritsFromItself
+            | Code::ThisOutsideClass
+            | Code::LoopControlOutsideLoop => Phase::Resolve,
+            Code::Runtime => Phase::Runtime,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::UnexpectedCharacter => "E0001",
+            Code::UnterminatedString => "E0002",
+            Code::UnterminatedComment => "E0003",
+            Code::Syntax => "E0100",
+            Code::DuplicateVariable => "E0200",
+            Code::ReadInOwnInitializer => "E0201",
+            Code::TopLevelReturn => "E0202",
+            Code::InitializerReturnsValue => "E0203",
+            Code::InheritsFromItself => "E0204",
+            Code::ThisOutsideClass => "E0205",
+            Code::LoopControlOutsideLoop => "E0206",
+            Code::Runtime => "E0300",
+        }
+    }
+}
+
+impl Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Notes and a suggestion printed under an error.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hints {
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Hints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn note(mut self, note: &str) -> Self {
+        self.notes.push(String::from(note));
+        self
+    }
+
+    pub fn help(mut self, help: &str) -> Self {
+        self.help = Some(String::from(help));
+        self
+    }
+}
+
+/// Renders errors in the style of rustc: the message, the location, the
+/// offending source line with the span underlined, and any hints.
+///
+/// ```text
+/// error[E0300]: Undefined variable 'self'.
+///  --> script.lox:1:7
+///   |
+/// 1 | print(self);
+///   |       ^^^^
+///   = help: did you mean 'this'?
+/// ```
+pub struct Renderer {
+    color: bool,
+}
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+impl Renderer {
+    pub fn new(color: bool) -> Self {
+        Self { color }
+    }
+
+    pub fn render(
+        &self,
+        out: &mut dyn Write,
+        file: &str,
+        source: &str,
+        diagnostic: &Diagnostic,
+    ) -> io::Result<()> {
+        let Diagnostic {
+            severity,
+            code,
+            span,
+            message,
+            hints,
+            trace,
+            ..
+        } = diagnostic;
+        let color = match severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        writeln!(
+            out,
+            "{}{}",
+            self.paint(color, &format!("{severity}[{code}]")),
+            self.paint(BOLD, &format!(": {message}"))
+        )?;
+
+        let mut gutter = String::new();
+        if let Some(span) = *span {
+            gutter = " ".repeat(span.line.to_string().len());
+            writeln!(
+                out,
+                "{gutter}{} {file}:{}:{}",
+                self.paint(BLUE, "-->"),
+                span.line,
+                span.column
+            )?;
+            if let Some((text, underline)) = snippet(source, span) {
+                let bar = self.paint(BLUE, "|");
+                writeln!(out, "{gutter} {bar}")?;
+                writeln!(
+                    out,
+                    "{} {bar} {text}",
+                    self.paint(BLUE, &span.line.to_string())
+                )?;
+                let (indent, carets) = underline;
+                writeln!(out, "{gutter} {bar} {indent}{}", self.paint(color, &carets))?;
+            }
+        }
+
+        let equals = self.paint(BLUE, "=");
+        for note in hints.notes.iter() {
+            writeln!(
+                out,
+                "{gutter} {equals} {}: {note}",
+                self.paint(BOLD, "note")
+            )?;
+        }
+        if let Some(help) = &hints.help {
+            writeln!(
+                out,
+                "{gutter} {equals} {}: {help}",
+                self.paint(BOLD, "help")
+            )?;
+        }
+        // A trace through the script alone would repeat the location.
+        if trace.len() > 1 {
+            writeln!(out, "{gutter} {equals} {}:", self.paint(BOLD, "backtrace"))?;
+            for frame in trace.iter() {
+                write!(out, "{gutter}     {}", frame.function)?;
+                if let Some(span) = frame.span {
+                    write!(out, " at {file}:{}:{}", span.line, span.column)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{style}{text}{RESET}")
+        } else {
+            String::from(text)
+        }
+    }
+}
+
+/// Finds the source line `span` starts on, together with the whitespace that
+/// lines up with the span and the carets underlining it. Spans running past
+/// the end of the line are underlined up to its end.
+fn snippet(source: &str, span: Span) -> Option<(&str, (String, String))> {
+    let before = source.get(..span.offset)?;
+    let start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let end = source[span.offset..]
+        .find('\n')
+        .map_or(source.len(), |newline| span.offset + newline);
+    let text = source[start..end].trim_end_matches('\r');
+
+    let indent = source[start..span.offset]
+        .chars()
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+    let underlined = source
+        .get(span.offset..(span.offset + span.length).min(end))
+        .map_or(0, |text| text.chars().count());
+    Some((text, (indent, "^".repeat(underlined.max(1)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_line_with_the_span_underlined() {
+        let source = "var a = 1;\nprint(self);\n";
+        let span = Span {
+            line: 2,
+            column: 7,
+            offset: 17,
+            length: 4,
+        };
+        let diagnostic = Diagnostic::error(Code::Runtime, Some(span), "Undefined variable 'self'.")
+            .with_hints(Hints::new().help("did you mean 'this'?"));
+
+        assert_eq!(
+            render(source, &diagnostic),
+            "\
+error[E0300]: Undefined variable 'self'.
+ --> script.lox:2:7
+  |
+2 | print(self);
+  |       ^^^^
+  = help: did you mean 'this'?
+"
+        );
+    }
+
+    #[test]
+    fn underlines_the_end_of_input_and_multiline_spans_up_to_the_line_end() {
+        let source = "print \"ab\ncd\"";
+        let at_end = Span {
+            line: 2,
+            column: 4,
+            offset: 13,
+            length: 0,
+        };
+        let diagnostic = Diagnostic::error(Code::Syntax, Some(at_end), "Expect ';' after value.");
+        assert_eq!(
+            render(source, &diagnostic),
+            "\
+error[E0100]: Expect ';' after value.
+ --> script.lox:2:4
+  |
+2 | cd\"
+  |    ^
+"
+        );
+
+        let string = Span {
+            line: 1,
+            column: 7,
+            offset: 6,
+            length: 7,
+        };
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(Code::Syntax, Some(string), "Oops.")
+        }
+        .with_hints(Hints::new().note("first").note("second"));
+        assert_eq!(
+            render(source, &diagnostic),
+            "\
+warning[E0100]: Oops.
+ --> script.lox:1:7
+  |
+1 | print \"ab
+  |       ^^^
+  = note: first
+  = note: second
+"
+        );
+    }
+
+    #[test]
+    fn renders_errors_without_a_span() {
+        let diagnostic = Diagnostic::error(Code::Runtime, None, "Print error: broken pipe");
+        assert_eq!(
+            render("", &diagnostic),
+            "error[E0300]: Print error: broken pipe\n"
+        );
+    }
+
+    #[test]
+    fn renders_backtraces() {
+        let span = |line, column| {
+            Some(Span {
+                line,
+                column,
+                offset: 0,
+                length: 1,
+            })
+        };
+        let diagnostic = Diagnostic::error(Code::Runtime, None, "Oops.").with_trace(vec![
+            TraceFrame {
+                function: String::from("print()"),
+                span: None,
+            },
+            TraceFrame {
+                function: String::from("f()"),
+                span: span(2, 8),
+            },
+            TraceFrame {
+                function: String::from("script"),
+                span: span(4, 2),
+            },
+        ]);
+        assert_eq!(
+            render("", &diagnostic),
+            "\
+error[E0300]: Oops.
+ = backtrace:
+     print()
+     f() at script.lox:2:8
+     script at script.lox:4:2
+"
+        );
+    }
+
+    #[test]
+    fn colors_the_output() {
+        let mut out = Vec::new();
+        let diagnostic = Diagnostic::error(Code::Runtime, None, "Oops.");
+        Renderer::new(true)
+            .render(&mut out, "a.lox", "", &diagnostic)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[1;31merror[E0300]\x1b[0m\x1b[1m: Oops.\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn displays_in_the_short_format() {
+        let span = Span {
+            line: 3,
+            column: 1,
+            offset: 20,
+            length: 1,
+        };
+        assert_eq!(
+            Diagnostic::error(Code::Syntax, Some(span), "Expect expression.")
+                .near("'+'")
+                .to_string(),
+            "[line 3] Error at '+': Expect expression."
+        );
+        assert_eq!(
+            Diagnostic::error(
+                Code::UnexpectedCharacter,
+                Some(span),
+                "Unexpected character"
+            )
+            .to_string(),
+            "[line 3] Error: Unexpected character"
+        );
+        assert_eq!(
+            Diagnostic::error(Code::Runtime, Some(span), "Operands must be numbers.").to_string(),
+            "Operands must be numbers.\n[line 3]"
+        );
+        assert_eq!(
+            Diagnostic::error(Code::Runtime, None, "Oops.").to_string(),
+            "Runtime error: Oops."
+        );
+    }
+
+    #[test]
+    fn writes_json() {
+        let span = Span {
+            line: 2,
+            column: 3,
+            offset: 13,
+            length: 6,
+        };
+        let diagnostic = Diagnostic::error(Code::TopLevelReturn, Some(span), "Can't \"return\".")
+            .with_hints(Hints::new().note("a\\b\n\u{1}"));
+        assert_eq!(
+            json(&diagnostic, "dir/a.lox"),
+            r#"{"phase":"resolve","severity":"error","code":"E0202","file":"dir/a.lox","line":2,"column":3,"length":6,"message":"Can't \"return\".","notes":["a\\b\n\u0001"],"help":null,"stack":[],"exit_code":65}
+"#
+        );
+
+        let trace = vec![
+            TraceFrame {
+                function: String::from("f()"),
+                span: None,
+            },
+            TraceFrame {
+                function: String::from("script"),
+                span: Some(Span {
+                    line: 1,
+                    column: 6,
+                    offset: 5,
+                    length: 1,
+                }),
+            },
+        ];
+        let diagnostic = Diagnostic::error(Code::Runtime, None, "Oops.")
+            .with_hints(Hints::new().help("did you mean 'this'?"))
+            .with_trace(trace);
+        assert_eq!(
+            json(&diagnostic, "a.lox"),
+            r#"{"phase":"runtime","severity":"error","code":"E0300","file":"a.lox","line":null,"column":null,"length":null,"message":"Oops.","notes":[],"help":"did you mean 'this'?","stack":[{"function":"f()","line":null,"column":null},{"function":"script","line":1,"column":6}],"exit_code":70}
+"#
+        );
+    }
+
+    fn json(diagnostic: &Diagnostic, file: &str) -> String {
+        let mut out = Vec::new();
+        diagnostic.write_json(&mut out, file).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn render(source: &str, diagnostic: &Diagnostic) -> String {
+        let mut out = Vec::new();
+        Renderer::new(false)
+            .render(&mut out, "script.lox", source, diagnostic)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+}
@@ -4,7 +4,7 @@ use std::{
     rc::{Rc, Weak},
 };
 
-use super::{error::RuntimeError, token::Token, value::Cell};
+use super::{diagnostic::Hints, error::RuntimeError, token::Token, value::Cell};
 
 #[derive(Debug)]
 pub struct Environment {
@@ -42,10 +42,7 @@ impl Environment {
         } else if let Some(enclosing) = &self.enclosing {
             enclosing.borrow().get(name)
         } else {
-            Err(RuntimeError::new(
-                name.to_owned(),
-                &format!("Undefined variable '{}'.", name.lexeme()),
-            ))
+            Err(undefined_variable(name))
         }
     }
 
@@ -60,15 +57,19 @@ impl Environment {
         } else if let Some(enclosing) = &self.enclosing {
             enclosing.borrow_mut().assign(name, value)
         } else {
-            Err(RuntimeError::new(
-                name.to_owned(),
-                &format!("Undefined variable '{}'.", name.lexeme()),
-            ))
+            Err(undefined_variable(name))
         }
     }
 
-    pub fn assing_at(&self, distance: usize, name: &Token, value: Cell) {
-        self.ancestor(distance)
+    pub fn assing_at(&mut self, distance: usize, name: &Token, value: Cell) {
+        if distance == 0 {
+            // `self` is already borrowed, so it can't be reached via `ancestor`.
+            self.values.insert(name.lexeme().to_owned(), value);
+            return;
+        }
+        let enclosing = self.enclosing.as_ref().expect("Environment exists");
+        let ancestor = enclosing.borrow().ancestor(distance - 1);
+        ancestor
             .borrow_mut()
             .values
             .insert(name.lexeme().to_owned(), value);
@@ -89,3 +90,15 @@ impl Environment {
         environment
     }
 }
+
+fn undefined_variable(name: &Token) -> RuntimeError {
+    let error = RuntimeError::new(
+        name.to_owned(),
+        &format!("Undefined variable '{}'.", name.lexeme()),
+    );
+    if name.lexeme().as_ref() == "self" {
+        error.with_hints(Hints::new().help("did you mean 'this'?"))
+    } else {
+        error
+    }
+}
@@ -1,67 +1,129 @@
-use std::{cell::Cell, error::Error, fmt::Display};
-
-use super::token::{Token, TokenKind};
+use std::{
+    cell::{self, RefCell},
+    error::Error,
+    fmt::Display,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use super::{
+    diagnostic::{Code, Diagnostic, Hints, Phase, Renderer, TraceFrame},
+    token::{Span, Token, TokenKind},
+    value::Cell,
+};
+
+/// How diagnostics are written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    /// `[line N] Error at 'x': message`, as in the book.
+    #[default]
+    Short,
+    /// The offending source line with the span underlined, as rustc does.
+    Human { color: bool },
+    /// One JSON object per line, for tools.
+    Json,
+}
 
+/// Collects the diagnostics found while running a script, so that they can
+/// be inspected or written out afterwards.
 pub struct ErrorReporter {
-    had_error: Cell<bool>,
-    had_runtime_error: Cell<bool>,
-    print_on_error: Cell<bool>,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    format: cell::Cell<ErrorFormat>,
+    file: RefCell<Option<String>>,
+    source: RefCell<Rc<str>>,
 }
 
 impl ErrorReporter {
     pub fn new() -> Self {
         Self {
-            had_error: Cell::new(false),
-            had_runtime_error: Cell::new(false),
-            print_on_error: Cell::new(true),
+            diagnostics: RefCell::new(Vec::new()),
+            format: cell::Cell::new(ErrorFormat::default()),
+            file: RefCell::new(None),
+            source: RefCell::new(Rc::from("")),
         }
     }
 
+    pub fn set_format(&self, format: ErrorFormat) {
+        self.format.set(format);
+    }
+
+    /// Names the file being run, so that diagnostics can point to
+    /// `file:line:column`.
+    pub fn set_file(&self, file: &str) {
+        self.file.replace(Some(String::from(file)));
+    }
+
+    /// Sets the source that spans of reported diagnostics refer to.
+    pub fn set_source(&self, source: &str) {
+        self.source.replace(Rc::from(source));
+    }
+
     pub fn had_error(&self) -> bool {
-        self.had_error.get()
+        self.diagnostics
+            .borrow()
+            .iter()
+            .any(|diagnostic| diagnostic.phase() != Phase::Runtime)
     }
 
     pub fn had_runtime_error(&self) -> bool {
-        self.had_runtime_error.get()
+        self.diagnostics
+            .borrow()
+            .iter()
+            .any(|diagnostic| diagnostic.phase() == Phase::Runtime)
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.borrow().clone()
     }
 
-    pub fn error(&self, line: usize, message: &str) {
-        self.report(line, "", message);
+    pub fn error(&self, span: Span, code: Code, message: &str) {
+        self.report(Diagnostic::error(code, Some(span), message));
     }
 
     pub fn reset(&self) {
-        self.had_error.set(false);
-        self.had_runtime_error.set(false);
+        self.diagnostics.borrow_mut().clear();
     }
 
-    fn report(&self, line: usize, where_part: &str, message: &str) {
-        if self.print_on_error.get() {
-            eprintln!("[line {line}] Error{where_part}: {message}");
-        }
-        self.had_error.set(true)
+    pub fn report(&self, diagnostic: Diagnostic) {
+        self.diagnostics.borrow_mut().push(diagnostic);
     }
 
-    pub fn token_error(&self, token: &Token, message: &str) {
-        if token.kind == TokenKind::Eof {
-            self.report(token.line, " at end", message)
+    pub fn token_error(&self, token: &Token, code: Code, message: &str) {
+        self.token_error_with_hints(token, code, message, Hints::new())
+    }
+
+    pub fn token_error_with_hints(&self, token: &Token, code: Code, message: &str, hints: Hints) {
+        let near = if token.kind == TokenKind::Eof {
+            String::from("end")
         } else {
-            self.report(token.line, &format!(" at '{}'", token.lexeme()), message)
-        }
+            format!("'{}'", token.lexeme())
+        };
+        self.report(
+            Diagnostic::error(code, Some(token.span()), message)
+                .near(&near)
+                .with_hints(hints),
+        )
     }
 
     pub fn runtime_error(&self, error: &RuntimeError) {
-        eprintln!("{error}");
-        self.had_runtime_error.set(true);
+        self.report(Diagnostic::from(error));
     }
 
-    pub fn run_without_printing_error<F, R>(&self, mut action: F) -> R
-    where
-        F: FnMut() -> R,
-    {
-        self.print_on_error.set(false);
-        let result = action();
-        self.print_on_error.set(true);
-        result
+    /// Writes the collected diagnostics in the chosen format.
+    pub fn write_diagnostics(&self, out: &mut dyn Write) -> io::Result<()> {
+        let file = self.file.borrow();
+        let file = file.as_deref().unwrap_or("<input>");
+        let source = self.source.borrow();
+        for diagnostic in self.diagnostics.borrow().iter() {
+            match self.format.get() {
+                ErrorFormat::Short => writeln!(out, "{diagnostic}")?,
+                ErrorFormat::Human { color } => {
+                    Renderer::new(color).render(out, file, &source, diagnostic)?
+                }
+                ErrorFormat::Json => diagnostic.write_json(out, file)?,
+            }
+        }
+        Ok(())
     }
 }
 
@@ -73,17 +135,63 @@ impl Default for ErrorReporter {
 
 #[derive(Debug)]
 pub struct RuntimeError {
-    pub token: Option<Token>,
+    pub token: Option<Box<Token>>,
     pub message: String,
+    /// The value of a `throw` statement, `None` for built-in errors.
+    pub value: Option<Cell>,
+    /// Boxed to keep `Result`s carrying the error small.
+    pub hints: Box<Hints>,
+    /// Filled in by the interpreter as the error leaves the function that
+    /// raised it.
+    pub trace: Rc<[TraceFrame]>,
 }
 
 impl RuntimeError {
     pub fn new(token: Token, message: &str) -> Self {
         Self {
-            token: Some(token),
+            token: Some(Box::new(token)),
             message: String::from(message),
+            value: None,
+            hints: Box::default(),
+            trace: Rc::from([]),
+        }
+    }
+
+    /// An error raised by `throw`. It is reported with the `message` field of
+    /// the value if it has one, and the value itself otherwise.
+    pub fn thrown(keyword: Token, value: Cell) -> Self {
+        let message = match value.as_instance() {
+            Some(instance) => match instance.borrow().field("message") {
+                Some(message) => message.to_string(),
+                None => value.to_string(),
+            },
+            None => value.to_string(),
+        };
+        Self {
+            value: Some(value),
+            ..Self::new(keyword, &message)
         }
     }
+
+    pub fn with_hints(mut self, hints: Hints) -> Self {
+        self.hints = Box::new(hints);
+        self
+    }
+
+    /// Attaches `token` to an error that was raised without one, so that it
+    /// is reported with a line.
+    pub fn at(mut self, token: &Token) -> Self {
+        self.token.get_or_insert_with(|| Box::new(token.clone()));
+        self
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.token.as_ref().map(|token| token.line)
+    }
+
+    pub fn column(&self) -> Option<usize> {
+        self.token.as_ref().map(|token| token.column)
+    }
 }
 
 impl Display for RuntimeError {
@@ -101,8 +209,38 @@ impl From<String> for RuntimeError {
         Self {
             token: None,
             message,
+            value: None,
+            hints: Box::default(),
+            trace: Rc::from([]),
         }
     }
 }
 
 impl Error for RuntimeError {}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        let span = error.token.as_ref().map(|token| token.span());
+        Diagnostic::error(Code::Runtime, span, &error.message)
+            .with_hints(*error.hints.clone())
+            .with_trace(error.trace.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::walk_tree::token::TokenKind;
+
+    use super::*;
+
+    #[test]
+    fn runtime_errors_know_their_column() {
+        let token = Token::new(TokenKind::Plus, "+".into(), 2, 5, 12);
+        let error = RuntimeError::from(String::from("Operands must be numbers."));
+        assert_eq!(error.column(), None);
+
+        let error = error.at(&token);
+        assert_eq!(error.line(), Some(2));
+        assert_eq!(error.column(), Some(5));
+    }
+}
@@ -2,14 +2,18 @@ use std::process::ExitCode;
 
 // Source: https://www.freebsd.org/cgi/man.cgi?query=sysexits&apropos=0&sektion=0&manpath=FreeBSD+4.3-RELEASE&format=html
 
+pub const USAGE: u8 = 64;
+pub const DATA_ERR: u8 = 65;
+pub const SOFTWARE: u8 = 70;
+
 pub fn usage() -> ExitCode {
-    ExitCode::from(64)
+    ExitCode::from(USAGE)
 }
 
 pub fn data_err() -> ExitCode {
-    ExitCode::from(65)
+    ExitCode::from(DATA_ERR)
 }
 
 pub fn software() -> ExitCode {
-    ExitCode::from(70)
+    ExitCode::from(SOFTWARE)
 }
@@ -59,6 +59,13 @@ impl Callable for Function {
         self.parameters.len()
     }
 
+    fn name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{}()", name.lexeme()),
+            None => String::from("<anonymous fn>"),
+        }
+    }
+
     fn call(
         &self,
         context: &mut dyn ExecutionContext,
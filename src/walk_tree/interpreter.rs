@@ -5,7 +5,7 @@ use std::io::Write;
 use std::rc::Rc;
 
 use crate::walk_tree::error::RuntimeError;
-use crate::walk_tree::stmt::Stmt;
+use crate::walk_tree::stmt::{Catch, Stmt};
 
 use super::callable::{Callable, ExecutionContext};
 use super::class::{Class, Instance};
@@ -15,6 +15,7 @@ use super::function::Function;
 use super::native;
 use super::resolver::Resolve;
 use super::{
+    diagnostic::TraceFrame,
     error::ErrorReporter,
     expr::{Expr, Function as FunctionExpr},
     token::{Token, TokenKind},
@@ -28,6 +29,14 @@ pub struct Interpreter<'a, W> {
     locals: HashMap<*const Expr, usize>,
     this_keyword: Rc<str>,
     super_keyword: Rc<str>,
+    call_stack: Vec<CallFrame>,
+    error_class: Rc<Class>,
+}
+
+/// A call that is being run, for stack traces.
+struct CallFrame {
+    callee: Rc<dyn Callable>,
+    call_site: Token,
 }
 
 impl<'a, W> Interpreter<'a, W>
@@ -44,6 +53,8 @@ where
             locals: HashMap::new(),
             this_keyword: Rc::from("this"),
             super_keyword: Rc::from("super"),
+            call_stack: Vec::new(),
+            error_class: Class::new(Rc::from("Error"), None, HashMap::new()),
         }
     }
 
@@ -60,7 +71,7 @@ where
         let env = Rc::clone(&self.globals);
         for statement in statements {
             if let Err(ControlFlow::RuntimeError(error)) = self.execute(statement, &env) {
-                self.error_reporter.runtime_error(&error);
+                self.error_reporter.runtime_error(&self.trace(error));
                 return;
             }
         }
@@ -113,14 +124,17 @@ where
     }
 
     pub fn evaluate_and_print(&mut self, expr: &Expr) -> Result<Cell> {
-        let result = self.evaluate(expr, &Rc::clone(&self.globals));
-        match &result {
+        match self.evaluate(expr, &Rc::clone(&self.globals)) {
             Ok(result) => {
                 writeln!(self.output, "{result}")?;
+                Ok(result)
+            }
+            Err(error) => {
+                let error = self.trace(error);
+                self.error_reporter.runtime_error(&error);
+                Err(anyhow!("Evaluate error: {}", error))
             }
-            Err(error) => self.error_reporter.runtime_error(error),
         }
-        result.map_err(|err| anyhow!("Evaluate error: {}", err))
     }
 
     fn execute(&mut self, stmt: &Stmt, env: &Rc<RefCell<Environment>>) -> Result<(), ControlFlow> {
@@ -135,7 +149,13 @@ where
             Stmt::Return { keyword, expr } => {
                 self.execute_return_stmt(keyword, expr.as_deref(), env)
             }
-            Stmt::While { condition, body } => self.execute_while_stmt(condition, body, env),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => self.execute_while_stmt(condition, body, increment.as_deref(), env),
+            Stmt::Break { .. } => Err(ControlFlow::Break),
+            Stmt::Continue { .. } => Err(ControlFlow::Continue),
             Stmt::VarDeclaration { name, initializer } => {
                 self.execute_var_stmt(name, initializer.as_deref(), env)
             }
@@ -144,6 +164,12 @@ where
                 superclass,
                 methods,
             } => self.execute_class_stmt(name, superclass.as_deref(), methods, env),
+            Stmt::Throw { keyword, value } => self.execute_throw_stmt(keyword, value, env),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+            } => self.execute_try_stmt(body, catch.as_ref(), finally.as_deref(), env),
         }
     }
 
@@ -213,10 +239,18 @@ where
         &mut self,
         condition: &Expr,
         body: &Stmt,
+        increment: Option<&Expr>,
         env: &Rc<RefCell<Environment>>,
     ) -> Result<(), ControlFlow> {
         while self.evaluate(condition, env)?.is_truthy() {
-            self.execute(body, env)?
+            match self.execute(body, env) {
+                Ok(()) | Err(ControlFlow::Continue) => {}
+                Err(ControlFlow::Break) => break,
+                Err(control_flow) => return Err(control_flow),
+            }
+            if let Some(increment) = increment {
+                self.evaluate(increment, env)?;
+            }
         }
         Ok(())
     }
@@ -230,7 +264,8 @@ where
     ) -> Result<Cell, RuntimeError> {
         let value = self.evaluate(value, env)?;
         if let Some(distance) = self.locals.get(&expr) {
-            env.borrow().assing_at(*distance, name, value.to_owned())
+            env.borrow_mut()
+                .assing_at(*distance, name, value.to_owned())
         } else {
             self.globals.borrow_mut().assign(name, value.to_owned())?;
         }
@@ -330,7 +365,7 @@ where
 
         let arguments = self.evaluate_exprs(arguments, env)?;
 
-        let function = <Rc<dyn Callable>>::try_from(callee)?;
+        let function = <Rc<dyn Callable>>::try_from(callee).map_err(|error| error.at(paren))?;
         if arguments.len() != function.arity() {
             Self::runtime_error(
                 paren.to_owned(),
@@ -341,8 +376,39 @@ where
                 ),
             )
         } else {
-            function.call(self, &arguments)
+            self.call_stack.push(CallFrame {
+                callee: Rc::clone(&function),
+                call_site: paren.to_owned(),
+            });
+            let result = function
+                .call(self, &arguments)
+                .map_err(|error| self.trace(error));
+            self.call_stack.pop();
+            result
+        }
+    }
+
+    /// Records the calls being run in an error that has no trace yet, that is
+    /// as it leaves the innermost of them.
+    fn trace(&self, mut error: RuntimeError) -> RuntimeError {
+        if !error.trace.is_empty() {
+            return error;
         }
+        let mut trace = Vec::with_capacity(self.call_stack.len() + 1);
+        let mut span = error.token.as_ref().map(|token| token.span());
+        for frame in self.call_stack.iter().rev() {
+            trace.push(TraceFrame {
+                function: frame.callee.name(),
+                span,
+            });
+            span = Some(frame.call_site.span());
+        }
+        trace.push(TraceFrame {
+            function: String::from("script"),
+            span,
+        });
+        error.trace = Rc::from(trace);
+        error
     }
 
     fn evaluate_exprs(
@@ -412,6 +478,63 @@ where
         }
     }
 
+    fn execute_throw_stmt(
+        &mut self,
+        keyword: &Token,
+        value: &Expr,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), ControlFlow> {
+        let value = self.evaluate(value, env)?;
+        Err(ControlFlow::from(RuntimeError::thrown(
+            keyword.to_owned(),
+            value,
+        )))
+    }
+
+    fn execute_try_stmt(
+        &mut self,
+        body: &[Box<Stmt>],
+        catch: Option<&Catch>,
+        finally: Option<&[Box<Stmt>]>,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), ControlFlow> {
+        let result = match (self.execute_block_stmt(body, env), catch) {
+            (Err(ControlFlow::RuntimeError(error)), Some(catch)) => {
+                let environment = Environment::new_with_enclosing(Rc::clone(env));
+                environment
+                    .borrow_mut()
+                    .define(Rc::clone(catch.name.lexeme()), self.caught_value(error));
+                self.execute_block(&catch.body, &environment)
+            }
+            (result, _) => result,
+        };
+        if let Some(finally) = finally {
+            self.execute_block_stmt(finally, env)?;
+        }
+        result
+    }
+
+    /// The value a `catch` clause binds: what was thrown, or an `Error`
+    /// instance with the `message` and `line` of a built-in error.
+    fn caught_value(&self, error: RuntimeError) -> Cell {
+        if let Some(value) = error.value {
+            return value;
+        }
+        let line = error
+            .line()
+            .map_or(Cell::from(()), |line| Cell::from(line as f64));
+        Cell::from(Instance::with_fields(
+            Rc::clone(&self.error_class),
+            [
+                (
+                    Rc::from("message"),
+                    Cell::from(Rc::<str>::from(error.message)),
+                ),
+                (Rc::from("line"), line),
+            ],
+        ))
+    }
+
     fn execute_class_stmt(
         &mut self,
         name: &Token,
@@ -484,7 +607,7 @@ where
         env: &Rc<RefCell<Environment>>,
     ) -> Result<Cell, RuntimeError> {
         let object = self.evaluate(object, env)?;
-        let instance = <Rc<RefCell<Instance>>>::try_from(object)?;
+        let instance = <Rc<RefCell<Instance>>>::try_from(object).map_err(|error| error.at(name))?;
         let value = instance.borrow().get(name)?;
         Ok(value)
     }
@@ -497,7 +620,7 @@ where
         env: &Rc<RefCell<Environment>>,
     ) -> Result<Cell, RuntimeError> {
         let object = self.evaluate(object, env)?;
-        let instance = <Rc<RefCell<Instance>>>::try_from(object)?;
+        let instance = <Rc<RefCell<Instance>>>::try_from(object).map_err(|error| error.at(name))?;
         let value = self.evaluate(value, env)?;
         instance.borrow_mut().set(name, value.clone());
         Ok(value)
@@ -592,7 +715,9 @@ impl<'a, W> Resolve for Interpreter<'a, W> {
 mod tests {
     use std::io;
 
-    use crate::walk_tree::{parser::Parser, resolver::Resolver, scanner::Scanner};
+    use crate::walk_tree::{
+        diagnostic::Diagnostic, parser::Parser, resolver::Resolver, scanner::Scanner,
+    };
     use anyhow::Context;
 
     use super::*;
@@ -1083,6 +1208,192 @@ mod tests {
         )
     }
 
+    #[test]
+    fn runtime_errors_have_a_stack_trace() {
+        let diagnostic = test_runtime_error(
+            r#"
+fun inner(x) {
+  return -x;
+}
+var outer = fun () {
+  return inner("a");
+};
+class A {
+  init() { outer(); }
+}
+A();
+"#,
+        );
+        let trace: Vec<_> = diagnostic
+            .trace
+            .iter()
+            .map(|frame| {
+                let span = frame.span.unwrap();
+                (frame.function.as_str(), span.line, span.column)
+            })
+            .collect();
+        assert_eq!(
+            trace,
+            vec![
+                ("inner()", 3, 10),
+                ("<anonymous fn>", 6, 19),
+                ("A()", 9, 18),
+                ("script", 11, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn top_level_errors_are_traced_to_the_script() {
+        let diagnostic = test_runtime_error("\n  -nil;");
+        assert_eq!(
+            diagnostic.trace,
+            vec![TraceFrame {
+                function: String::from("script"),
+                span: diagnostic.span,
+            }]
+        );
+        assert_eq!(diagnostic.span.map(|span| span.column), Some(3));
+    }
+
+    #[test]
+    fn catches_thrown_values() {
+        assert_prints(
+            r#"
+        fun fail() { throw "oops"; }
+        try {
+          fail();
+          print("unreachable");
+        } catch (e) {
+          print(e);
+        }
+        print("after");
+        "#,
+            b"oops\nafter\n",
+        );
+    }
+
+    #[test]
+    fn catches_builtin_errors() {
+        assert_prints(
+            r#"
+        try {
+          -"a";
+        } catch (e) {
+          print(e.message);
+          print(e.line);
+        }
+        try { print(missing); } catch (e) { print(e.message); }
+        fun f(a) {}
+        try { f(1, 2); } catch (e) { print(e.message); }
+        "#,
+            b"Operand must be a number.\n3\nUndefined variable 'missing'.\n\
+Expected 1 arguments but got 2.\n",
+        );
+    }
+
+    #[test]
+    fn finally_always_runs() {
+        assert_prints(
+            r#"
+        fun f() {
+          try { return "returned"; } finally { print("finally"); }
+        }
+        print(f());
+        try {
+          try { throw "inner"; } finally { print("cleanup"); }
+        } catch (e) {
+          print(e);
+        }
+        try {
+          try { throw "first"; } catch (e) { throw e + " again"; }
+        } catch (e) {
+          print(e);
+        }
+        "#,
+            b"finally\nreturned\ncleanup\ninner\nfirst again\n",
+        );
+    }
+
+    #[test]
+    fn assigns_to_a_local_in_the_same_block() {
+        assert_prints("{ var a = 1; a = 2; print(a); }", b"2\n");
+    }
+
+    #[test]
+    fn break_leaves_the_innermost_loop() {
+        assert_prints(
+            r#"
+        var i = 0;
+        while (true) {
+          i = i + 1;
+          for (var j = 0; j < 3; j = j + 1) {
+            if (j == 1) break;
+            print(j);
+          }
+          if (i == 2) break;
+        }
+        print(i);
+        "#,
+            b"0\n0\n2\n",
+        );
+    }
+
+    #[test]
+    fn continue_runs_the_for_increment() {
+        assert_prints(
+            r#"
+        for (var i = 0; i < 5; i = i + 1) {
+          if (i == 1 or i == 3) continue;
+          print(i);
+        }
+        var n = 0;
+        while (n < 3) {
+          n = n + 1;
+          if (n == 2) continue;
+          print(n);
+        }
+        "#,
+            b"0\n2\n4\n1\n3\n",
+        );
+    }
+
+    #[test]
+    fn break_runs_finally() {
+        assert_prints(
+            r#"
+        while (true) {
+          try { break; } finally { print("finally"); }
+        }
+        print("done");
+        "#,
+            b"finally\ndone\n",
+        );
+    }
+
+    #[test]
+    fn uncaught_throws_report_the_message_field() {
+        let diagnostic = test_runtime_error(
+            r#"
+class Failure { init(message) { this.message = message; } }
+throw Failure("bad input");
+"#,
+        );
+        assert_eq!(diagnostic.message, "bad input");
+        assert_eq!(diagnostic.span.map(|span| span.line), Some(3));
+    }
+
+    #[test]
+    fn type_errors_report_the_line_of_the_call_or_property() {
+        for source in ["\n\"a\"();", "\n1.b;", "\n1.b = 2;"] {
+            let error = test_interpret_stmt_expr(source).unwrap_err();
+            assert!(
+                error.root_cause().to_string().ends_with("\n[line 2]"),
+                "{source:?}: {error:#}"
+            );
+        }
+    }
+
     fn assert_evaluates_to<T>(source: &str, value: T)
     where
         Cell: From<T>,
@@ -1112,6 +1423,18 @@ mod tests {
         Ok(output)
     }
 
+    fn test_runtime_error(source: &str) -> Diagnostic {
+        let error_reporter = ErrorReporter::new();
+        let tree = test_parse(source, &error_reporter).unwrap();
+        let mut interpreter = Interpreter::new_with_output(&error_reporter, io::sink());
+        let mut resolver = Resolver::new(&mut interpreter, &error_reporter);
+        resolver.resolve(&tree);
+        interpreter.interpret(&tree);
+        let mut diagnostics = error_reporter.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        diagnostics.remove(0)
+    }
+
     fn test_interpret_stmt_expr(source: &str) -> Result<Cell> {
         let error_reporter = ErrorReporter::new();
         let tree = test_parse(source, &error_reporter).context("Parse error")?;
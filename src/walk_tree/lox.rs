@@ -1,4 +1,8 @@
-use std::{fs, io::Write, process::ExitCode};
+use std::{
+    fs,
+    io::{self, Write},
+    process::ExitCode,
+};
 
 use anyhow::Result;
 use rustyline::{error::ReadlineError, Editor};
@@ -30,7 +34,9 @@ where
 
     pub fn run_file(&mut self, path: &str) -> Result<ExitCode> {
         let source = fs::read_to_string(path)?;
+        self.error_reporter.set_file(path);
         self.run(source);
+        self.error_reporter.write_diagnostics(&mut io::stderr())?;
         Ok(if self.error_reporter.had_error() {
             exit_code::data_err()
         } else if self.error_reporter.had_runtime_error() {
@@ -68,21 +74,19 @@ where
     }
 
     fn run_interactively(&mut self, line: String) {
-        let result = self
-            .error_reporter
-            .run_without_printing_error(|| self.try_evaluate_expression(&line));
-        if result.is_err() {
-            self.error_reporter.reset();
-            return;
-        }
-        if self.error_reporter.had_error() {
+        if self.try_evaluate_expression(&line).is_ok() && self.error_reporter.had_error() {
+            // Not an expression, so try it as statements instead.
             self.error_reporter.reset();
             self.run(line);
         }
+        if let Err(err) = self.error_reporter.write_diagnostics(&mut io::stderr()) {
+            eprintln!("Write error: {err}");
+        }
         self.error_reporter.reset();
     }
 
     fn run(&mut self, source: String) {
+        self.error_reporter.set_source(&source);
         let tokens: Vec<_> = self.scanner.scan_tokens(&source).collect();
         let mut parser = Parser::new(tokens, self.error_reporter);
         let statements = parser.parse().unwrap_or_default();
@@ -100,6 +104,7 @@ where
     }
 
     fn try_evaluate_expression(&mut self, source: &str) -> Result<()> {
+        self.error_reporter.set_source(source);
         let tokens: Vec<_> = self.scanner.scan_tokens(source).collect();
         let mut parser = Parser::new(tokens, self.error_reporter);
         if let Some(expression) = parser.expression() {
@@ -111,6 +116,7 @@ where
 
 #[cfg(test)]
 mod tests {
+    use crate::walk_tree::diagnostic::{Code, Phase};
 
     use super::*;
 
@@ -137,6 +143,65 @@ mod tests {
         )
     }
 
+    #[test]
+    fn collects_diagnostics_from_every_phase() {
+        let error_reporter = ErrorReporter::new();
+        let mut lox = Lox::new(&error_reporter, Vec::new());
+
+        lox.run("var a = 1;\n  return a;".into());
+        let diagnostics = error_reporter.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Code::TopLevelReturn);
+        assert_eq!(diagnostics[0].phase(), Phase::Resolve);
+        assert_eq!(diagnostics[0].near.as_deref(), Some("'return'"));
+        assert_eq!(
+            diagnostics[0].span.map(|span| (span.line, span.column)),
+            Some((2, 3))
+        );
+        assert!(error_reporter.had_error());
+        assert!(!error_reporter.had_runtime_error());
+
+        error_reporter.reset();
+        lox.run("var b = @;".into());
+        let codes: Vec<_> = error_reporter
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert_eq!(codes, vec![Code::UnexpectedCharacter, Code::Syntax]);
+
+        error_reporter.reset();
+        lox.run("print(-\"x\");".into());
+        let diagnostics = error_reporter.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].phase(), Phase::Runtime);
+        assert!(!error_reporter.had_error());
+        assert!(error_reporter.had_runtime_error());
+
+        let mut out = Vec::new();
+        error_reporter.write_diagnostics(&mut out).unwrap();
+        assert_eq!(out, b"Operand must be a number.\n[line 1]\n");
+    }
+
+    #[test]
+    fn rejects_loop_control_outside_of_loops() {
+        let error_reporter = ErrorReporter::new();
+        let mut lox = Lox::new(&error_reporter, Vec::new());
+
+        lox.run("break;\nwhile (true) { fun f() { continue; } break; }".into());
+        let mut out = Vec::new();
+        error_reporter.write_diagnostics(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[line 1] Error at 'break': Can't use 'break' outside of a loop.\n\
+[line 2] Error at 'continue': Can't use 'continue' outside of a loop.\n"
+        );
+        assert!(error_reporter
+            .diagnostics()
+            .iter()
+            .all(|diagnostic| diagnostic.code == Code::LoopControlOutsideLoop));
+    }
+
     fn assert_prints(source: Vec<String>, value: &[u8]) {
         assert_eq!(test_interpreter_output(source).unwrap(), value)
     }
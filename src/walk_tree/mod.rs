@@ -1,6 +1,7 @@
 pub mod callable;
 pub mod class;
 pub mod control_flow;
+pub mod diagnostic;
 pub mod environment;
 pub mod error;
 pub mod exit_code;
@@ -14,6 +14,10 @@ impl Callable for Clock {
         0
     }
 
+    fn name(&self) -> String {
+        String::from("clock()")
+    }
+
     fn call(
         &self,
         _context: &mut dyn ExecutionContext,
@@ -39,6 +43,10 @@ impl Callable for Print {
         1
     }
 
+    fn name(&self) -> String {
+        String::from("print()")
+    }
+
     fn call(
         &self,
         context: &mut dyn ExecutionContext,
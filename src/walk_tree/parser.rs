@@ -1,12 +1,13 @@
 use std::rc::Rc;
 
 use super::{
+    diagnostic::Code,
     error::ErrorReporter,
     expr::{Expr, Function},
     token::{Token, TokenKind},
     value::Cell,
 };
-use crate::walk_tree::stmt::Stmt;
+use crate::walk_tree::stmt::{Catch, Stmt};
 
 pub struct Parser<'a> {
     tokens: Vec<Token>,
@@ -25,7 +26,7 @@ impl<'a> Parser<'a> {
     const TERM_OPERATORS: [TokenKind; 2] = [TokenKind::Minus, TokenKind::Plus];
     const FACTOR_OPERATORS: [TokenKind; 2] = [TokenKind::Slash, TokenKind::Star];
     const UNARY_OPERATORS: [TokenKind; 2] = [TokenKind::Bang, TokenKind::Minus];
-    const SYNCHRONIZE: [TokenKind; 7] = [
+    const SYNCHRONIZE: [TokenKind; 9] = [
         TokenKind::Class,
         TokenKind::Fun,
         TokenKind::Var,
@@ -33,6 +34,8 @@ impl<'a> Parser<'a> {
         TokenKind::If,
         TokenKind::While,
         TokenKind::Return,
+        TokenKind::Throw,
+        TokenKind::Try,
     ];
 
     pub fn new(tokens: Vec<Token>, error_reporter: &'a ErrorReporter) -> Self {
@@ -57,7 +60,7 @@ impl<'a> Parser<'a> {
 
     fn declaration(&mut self) -> Option<Box<Stmt>> {
         let result = self.try_declaration();
-        if matches!(result, None) {
+        if result.is_none() {
             self.synchronize();
         }
         result
@@ -84,6 +87,14 @@ impl<'a> Parser<'a> {
             self.return_stmt()
         } else if self.match_one(&TokenKind::While) {
             self.while_statement()
+        } else if self.match_one(&TokenKind::Break) {
+            self.break_statement()
+        } else if self.match_one(&TokenKind::Continue) {
+            self.continue_statement()
+        } else if self.match_one(&TokenKind::Throw) {
+            self.throw_statement()
+        } else if self.match_one(&TokenKind::Try) {
+            self.try_statement()
         } else if self.match_one(&TokenKind::LeftBrace) {
             self.block()
         } else {
@@ -117,14 +128,12 @@ impl<'a> Parser<'a> {
             "Expect ')' after for clauses.".into()
         })?;
 
-        let mut body = self.statement()?;
-        if let Some(increment) = increment {
-            body = Box::new(Stmt::Block(Rc::new([
-                body,
-                Box::new(Stmt::Expr(increment)),
-            ])));
-        }
-        body = Box::new(Stmt::While { condition, body });
+        let body = self.statement()?;
+        let mut body = Box::new(Stmt::While {
+            condition,
+            body,
+            increment,
+        });
         if let Some(initializer) = initializer {
             body = Box::new(Stmt::Block(Rc::new([initializer, body])));
         }
@@ -192,7 +201,77 @@ impl<'a> Parser<'a> {
             "Expect ')' after condition.".into()
         })?;
         let body = self.statement()?;
-        Some(Box::new(Stmt::While { condition, body }))
+        Some(Box::new(Stmt::While {
+            condition,
+            body,
+            increment: None,
+        }))
+    }
+
+    fn break_statement(&mut self) -> Option<Box<Stmt>> {
+        let keyword = self.previous().to_owned();
+        self.consume(&TokenKind::Semicolon, || "Expect ';' after 'break'.".into())?;
+        Some(Box::new(Stmt::Break { keyword }))
+    }
+
+    fn continue_statement(&mut self) -> Option<Box<Stmt>> {
+        let keyword = self.previous().to_owned();
+        self.consume(&TokenKind::Semicolon, || {
+            "Expect ';' after 'continue'.".into()
+        })?;
+        Some(Box::new(Stmt::Continue { keyword }))
+    }
+
+    fn throw_statement(&mut self) -> Option<Box<Stmt>> {
+        let keyword = self.previous().to_owned();
+        let value = self.expression()?;
+        self.consume(&TokenKind::Semicolon, || {
+            "Expect ';' after thrown value.".into()
+        })?;
+        Some(Box::new(Stmt::Throw { keyword, value }))
+    }
+
+    fn try_statement(&mut self) -> Option<Box<Stmt>> {
+        self.consume(&TokenKind::LeftBrace, || "Expect '{' after 'try'.".into())?;
+        let body = self.stmt_vec()?;
+
+        let catch = if self.match_one(&TokenKind::Catch) {
+            self.consume(&TokenKind::LeftParen, || "Expect '(' after 'catch'.".into())?;
+            let name = self
+                .consume(&TokenKind::Identifier, || {
+                    "Expect error variable name.".into()
+                })?
+                .to_owned();
+            self.consume(&TokenKind::RightParen, || {
+                "Expect ')' after error variable.".into()
+            })?;
+            self.consume(&TokenKind::LeftBrace, || {
+                "Expect '{' before catch body.".into()
+            })?;
+            Some(Catch {
+                name,
+                body: self.stmt_vec()?,
+            })
+        } else {
+            None
+        };
+        let finally = if self.match_one(&TokenKind::Finally) {
+            self.consume(&TokenKind::LeftBrace, || {
+                "Expect '{' after 'finally'.".into()
+            })?;
+            Some(self.stmt_vec()?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return self.error(self.peek(), "Expect 'catch' or 'finally' after try block.");
+        }
+        Some(Box::new(Stmt::Try {
+            body,
+            catch,
+            finally,
+        }))
     }
 
     fn expression_statement(&mut self) -> Option<Box<Stmt>> {
@@ -558,7 +637,8 @@ impl<'a> Parser<'a> {
     }
 
     fn error<T>(&self, token: &Token, message: &str) -> Option<T> {
-        self.error_reporter.token_error(token, message);
+        self.error_reporter
+            .token_error(token, Code::Syntax, message);
         None
     }
 
@@ -604,7 +684,7 @@ mod tests {
             test_parse_expr("2+2").unwrap().as_ref(),
             &Expr::binary(
                 Box::new(Expr::from(2.0)),
-                Token::new(TokenKind::Plus, "+".into(), 1),
+                Token::new(TokenKind::Plus, "+".into(), 1, 2, 1),
                 Box::new(Expr::from(2.0))
             )
         );
@@ -612,10 +692,10 @@ mod tests {
             test_parse_expr("1+2*3").unwrap().as_ref(),
             &Expr::binary(
                 Box::new(Expr::from(1.0)),
-                Token::new(TokenKind::Plus, "+".into(), 1),
+                Token::new(TokenKind::Plus, "+".into(), 1, 2, 1),
                 Box::new(Expr::binary(
                     Box::new(Expr::from(2.0)),
-                    Token::new(TokenKind::Star, "*".into(), 1),
+                    Token::new(TokenKind::Star, "*".into(), 1, 4, 3),
                     Box::new(Expr::from(3.0))
                 ))
             )
@@ -625,10 +705,10 @@ mod tests {
             &Expr::binary(
                 Box::new(Expr::Grouping(Box::new(Expr::binary(
                     Box::new(Expr::from(1.0)),
-                    Token::new(TokenKind::Plus, "+".into(), 1),
+                    Token::new(TokenKind::Plus, "+".into(), 1, 3, 2),
                     Box::new(Expr::from(2.0))
                 )))),
-                Token::new(TokenKind::Star, "*".into(), 1),
+                Token::new(TokenKind::Star, "*".into(), 1, 6, 5),
                 Box::new(Expr::from(3.0))
             )
         );
@@ -637,10 +717,10 @@ mod tests {
             &Expr::binary(
                 Box::new(Expr::binary(
                     Box::new(Expr::from(1.0)),
-                    Token::new(TokenKind::Plus, "+".into(), 1),
+                    Token::new(TokenKind::Plus, "+".into(), 1, 3, 2),
                     Box::new(Expr::from(2.0))
                 )),
-                Token::new(TokenKind::Plus, "+".into(), 1),
+                Token::new(TokenKind::Plus, "+".into(), 1, 7, 6),
                 Box::new(Expr::from(3.0)),
             )
         );
@@ -652,7 +732,7 @@ mod tests {
             test_parse_expr("2 < 3").unwrap().as_ref(),
             &Expr::binary(
                 Box::new(Expr::from(2.0)),
-                Token::new(TokenKind::Less, "<".into(), 1),
+                Token::new(TokenKind::Less, "<".into(), 1, 3, 2),
                 Box::new(Expr::from(3.0))
             )
         );
@@ -665,7 +745,7 @@ mod tests {
             &Expr::Ternary {
                 condition: Box::new(Expr::binary(
                     Box::new(Expr::from(2.0)),
-                    Token::new(TokenKind::Less, "<".into(), 1),
+                    Token::new(TokenKind::Less, "<".into(), 1, 3, 2),
                     Box::new(Expr::from(3.0))
                 )),
                 then_expr: Box::new(Expr::from(4.0)),
@@ -679,7 +759,7 @@ mod tests {
         assert_eq!(
             test_parse_expr("a = 3 ? 4 : 5").unwrap().as_ref(),
             &Expr::Assignment {
-                name: Token::new(TokenKind::Identifier, "a".into(), 1),
+                name: Token::new(TokenKind::Identifier, "a".into(), 1, 1, 0),
                 value: Box::new(Expr::Ternary {
                     condition: Box::new(Expr::from(3.0)),
                     then_expr: Box::new(Expr::from(4.0)),
@@ -689,6 +769,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn try_needs_catch_or_finally() {
+        let error_reporter = ErrorReporter::new();
+        let scanner = Scanner::new(&error_reporter);
+        let tokens: Vec<_> = scanner.scan_tokens("try { 1; } print(2);").collect();
+        Parser::new(tokens, &error_reporter).parse();
+        let messages: Vec<_> = error_reporter
+            .diagnostics()
+            .into_iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["[line 1] Error at 'print': Expect 'catch' or 'finally' after try block."]
+        );
+    }
+
     fn test_parse_expr(source: &str) -> Option<Box<Expr>> {
         let error_reporer = ErrorReporter::new();
         let scanner = Scanner::new(&error_reporer);
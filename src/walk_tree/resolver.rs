@@ -1,9 +1,10 @@
 use std::{collections::HashMap, rc::Rc};
 
 use super::{
+    diagnostic::{Code, Hints},
     error::ErrorReporter,
     expr::{Expr, Function},
-    stmt::Stmt,
+    stmt::{Catch, Stmt},
     token::Token,
 };
 
@@ -17,6 +18,7 @@ pub struct Resolver<'a> {
     scopes: Vec<HashMap<Rc<str>, bool>>,
     current_function: Option<FunctionType>,
     current_class: Option<ClassType>,
+    loop_depth: usize,
     this_keyword: Rc<str>,
     super_keyword: Rc<str>,
 }
@@ -29,6 +31,7 @@ impl<'a> Resolver<'a> {
             scopes: Vec::new(),
             current_function: None,
             current_class: None,
+            loop_depth: 0,
             this_keyword: Rc::from("this"),
             super_keyword: Rc::from("super"),
         }
@@ -51,7 +54,14 @@ impl<'a> Resolver<'a> {
                 keyword,
                 expr: value,
             } => self.resolve_return_stmt(keyword, value.as_deref()),
-            Stmt::While { condition, body } => self.resolve_while_stmt(condition, body),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => self.resolve_while_stmt(condition, body, increment.as_deref()),
+            Stmt::Break { keyword } | Stmt::Continue { keyword } => {
+                self.resolve_loop_control_stmt(keyword)
+            }
             Stmt::VarDeclaration { name, initializer } => {
                 self.resolve_var_stmt(name, initializer.as_deref())
             }
@@ -60,6 +70,12 @@ impl<'a> Resolver<'a> {
                 superclass,
                 methods,
             } => self.resolve_class_stmt(name, superclass.as_deref(), methods),
+            Stmt::Throw { value, .. } => self.resolve_expr(value),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+            } => self.resolve_try_stmt(body, catch.as_ref(), finally.as_deref()),
         }
     }
 
@@ -132,8 +148,11 @@ impl<'a> Resolver<'a> {
     fn declare(&mut self, name: &Token) {
         if let Some(scope) = self.scopes.last_mut() {
             if scope.contains_key(name.lexeme()) {
-                self.error_reporter
-                    .token_error(name, "Already a variable with this name in this scope.");
+                self.error_reporter.token_error(
+                    name,
+                    Code::DuplicateVariable,
+                    "Already a variable with this name in this scope.",
+                );
             }
             scope.insert(Rc::clone(name.lexeme()), false);
         }
@@ -147,9 +166,12 @@ impl<'a> Resolver<'a> {
 
     fn resolve_variable_expr(&mut self, expr: &Expr, name: &Token) {
         if let Some(scope) = self.scopes.last() {
-            if scope.get(name.lexeme()).map_or(false, |defined| !defined) {
-                self.error_reporter
-                    .token_error(name, "Can't read local variable in its own initializer.")
+            if scope.get(name.lexeme()) == Some(&false) {
+                self.error_reporter.token_error(
+                    name,
+                    Code::ReadInOwnInitializer,
+                    "Can't read local variable in its own initializer.",
+                )
             }
         }
         self.resolve_local(expr, name)
@@ -170,16 +192,17 @@ impl<'a> Resolver<'a> {
     }
 
     fn resolve_function_expr(&mut self, function: &Function) {
-        if let Some(name) = function.name() {
-            self.declare(name);
-            self.define(name)
-        }
+        // A named function is always the initializer of the variable
+        // declaration that already declared its name.
         self.resolve_function(function, FunctionType::Function);
     }
 
     fn resolve_function(&mut self, function: &Function, function_type: FunctionType) {
         let enclosing_function = self.current_function;
         self.current_function = Some(function_type);
+        // A function body starts outside of any loop, even when it is
+        // declared inside one.
+        let enclosing_loop_depth = std::mem::take(&mut self.loop_depth);
 
         self.begin_scope();
         for param in function.parameters().iter() {
@@ -190,6 +213,7 @@ impl<'a> Resolver<'a> {
         self.end_scope();
 
         self.current_function = enclosing_function;
+        self.loop_depth = enclosing_loop_depth;
     }
 
     fn resolve_expression_stmt(&mut self, expression: &Expr) {
@@ -211,22 +235,63 @@ impl<'a> Resolver<'a> {
 
     fn resolve_return_stmt(&mut self, keyword: &Token, value: Option<&Expr>) {
         if self.current_function.is_none() {
-            self.error_reporter
-                .token_error(keyword, "Can't return from top-level code.")
+            self.error_reporter.token_error(
+                keyword,
+                Code::TopLevelReturn,
+                "Can't return from top-level code.",
+            )
         }
 
         if let Some(value) = value {
             if self.current_function == Some(FunctionType::Initializer) {
-                self.error_reporter
-                    .token_error(keyword, "Can't return a value from an initializer.")
+                self.error_reporter.token_error_with_hints(
+                    keyword,
+                    Code::InitializerReturnsValue,
+                    "Can't return a value from an initializer.",
+                    Hints::new().note("initializers always return 'this'"),
+                )
             }
             self.resolve_expr(value)
         }
     }
 
-    fn resolve_while_stmt(&mut self, condition: &Expr, body: &Stmt) {
+    fn resolve_try_stmt(
+        &mut self,
+        body: &[Box<Stmt>],
+        catch: Option<&Catch>,
+        finally: Option<&[Box<Stmt>]>,
+    ) {
+        self.resolve_block_stmt(body);
+        if let Some(catch) = catch {
+            self.begin_scope();
+            self.declare(&catch.name);
+            self.define(&catch.name);
+            self.resolve_stmts(&catch.body);
+            self.end_scope();
+        }
+        if let Some(finally) = finally {
+            self.resolve_block_stmt(finally);
+        }
+    }
+
+    fn resolve_while_stmt(&mut self, condition: &Expr, body: &Stmt, increment: Option<&Expr>) {
         self.resolve_expr(condition);
-        self.resolve_stmt(body)
+        self.loop_depth += 1;
+        self.resolve_stmt(body);
+        self.loop_depth -= 1;
+        if let Some(increment) = increment {
+            self.resolve_expr(increment)
+        }
+    }
+
+    fn resolve_loop_control_stmt(&mut self, keyword: &Token) {
+        if self.loop_depth == 0 {
+            self.error_reporter.token_error(
+                keyword,
+                Code::LoopControlOutsideLoop,
+                &format!("Can't use '{}' outside of a loop.", keyword.lexeme()),
+            )
+        }
     }
 
     fn resolve_binary_expr(&mut self, left: &Expr, right: &Expr) {
@@ -275,8 +340,11 @@ impl<'a> Resolver<'a> {
         if let Some(superclass) = superclass {
             let superclass_name = superclass.as_variable().expect("Expect identifier.");
             if name.lexeme() == superclass_name.lexeme() {
-                self.error_reporter
-                    .token_error(superclass_name, "A class can't inherit from itself.");
+                self.error_reporter.token_error(
+                    superclass_name,
+                    Code::InheritsFromItself,
+                    "A class can't inherit from itself.",
+                );
             }
 
             self.resolve_expr(superclass);
@@ -295,7 +363,7 @@ impl<'a> Resolver<'a> {
         for method in methods {
             let declaration = if method
                 .name()
-                .map_or(false, |name| name.lexeme().as_ref() == "init")
+                .is_some_and(|name| name.lexeme().as_ref() == "init")
             {
                 FunctionType::Initializer
             } else {
@@ -322,8 +390,11 @@ impl<'a> Resolver<'a> {
 
     fn resolve_this_expr(&mut self, expr: &Expr, keyword: &Token) {
         if self.current_class.is_none() {
-            self.error_reporter
-                .token_error(keyword, "Can't use 'this' outside of a class.");
+            self.error_reporter.token_error(
+                keyword,
+                Code::ThisOutsideClass,
+                "Can't use 'this' outside of a class.",
+            );
             return;
         }
 
@@ -346,3 +417,28 @@ enum FunctionType {
 enum ClassType {
     Class,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::walk_tree::{interpreter::Interpreter, parser::Parser, scanner::Scanner};
+
+    use super::*;
+
+    #[test]
+    fn local_functions_are_declared_once() {
+        assert!(resolves("{ fun f() {} }"));
+        assert!(resolves("{ fun f(n) { if (n > 0) f(n - 1); } f(2); }"));
+        assert!(!resolves("{ var f; fun f() {} }"));
+    }
+
+    fn resolves(source: &str) -> bool {
+        let error_reporter = ErrorReporter::new();
+        let tokens: Vec<_> = Scanner::new(&error_reporter).scan_tokens(source).collect();
+        let tree = Parser::new(tokens, &error_reporter).parse().unwrap();
+        let mut interpreter = Interpreter::new_with_output(&error_reporter, io::sink());
+        Resolver::new(&mut interpreter, &error_reporter).resolve(&tree);
+        !error_reporter.had_error()
+    }
+}
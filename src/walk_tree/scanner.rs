@@ -1,8 +1,9 @@
 use std::{collections::HashMap, rc::Rc};
 
 use super::{
+    diagnostic::Code,
     error::ErrorReporter,
-    token::{Token, TokenKind},
+    token::{Span, Token, TokenKind},
 };
 
 pub struct Scanner<'a> {
@@ -18,16 +19,20 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    pub fn scan_tokens(&self, source: &str) -> ScanTokens {
+    pub fn scan_tokens(&self, source: &str) -> ScanTokens<'_> {
         ScanTokens::new(source, &self.keywords, self.error_reporter)
     }
 
     fn keywords() -> HashMap<&'a str, TokenKind> {
         HashMap::from([
             ("and", TokenKind::And),
+            ("break", TokenKind::Break),
+            ("catch", TokenKind::Catch),
             ("class", TokenKind::Class),
+            ("continue", TokenKind::Continue),
             ("else", TokenKind::Else),
             ("false", TokenKind::False),
+            ("finally", TokenKind::Finally),
             ("for", TokenKind::For),
             ("fun", TokenKind::Fun),
             ("if", TokenKind::If),
@@ -36,7 +41,9 @@ impl<'a> Scanner<'a> {
             ("return", TokenKind::Return),
             ("super", TokenKind::Super),
             ("this", TokenKind::This),
+            ("throw", TokenKind::Throw),
             ("true", TokenKind::True),
+            ("try", TokenKind::Try),
             ("var", TokenKind::Var),
             ("while", TokenKind::While),
         ])
@@ -48,6 +55,11 @@ pub struct ScanTokens<'a> {
     start: usize,
     current: usize,
     line: usize,
+    line_start: usize,
+    start_line: usize,
+    start_column: usize,
+    start_offset: usize,
+    offset: usize,
     consumed: bool,
     keywords: &'a HashMap<&'a str, TokenKind>,
     error_reporter: &'a ErrorReporter,
@@ -65,6 +77,11 @@ impl<'a> ScanTokens<'a> {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            start_offset: 0,
+            offset: 0,
             consumed: false,
             error_reporter,
         }
@@ -106,7 +123,7 @@ impl<'a> ScanTokens<'a> {
             '\r' => None,
             '\t' => None,
             '\n' => {
-                self.line += 1;
+                self.new_line();
                 None
             }
             '"' => self.string(),
@@ -116,7 +133,11 @@ impl<'a> ScanTokens<'a> {
                 } else if ch.is_ascii_alphabetic() {
                     self.identifier()
                 } else {
-                    self.error_reporter.error(self.line, "Unexpected character");
+                    self.error_reporter.error(
+                        self.lexeme_span(),
+                        Code::UnexpectedCharacter,
+                        "Unexpected character",
+                    );
                     None
                 }
             }
@@ -135,7 +156,7 @@ impl<'a> ScanTokens<'a> {
         while !self.is_at_end() && nest > 0 {
             let ch = self.advance();
             if ch == '\n' {
-                self.line += 1;
+                self.new_line();
             }
             if ch == '/' && self.match_char('*') {
                 nest += 1;
@@ -144,20 +165,24 @@ impl<'a> ScanTokens<'a> {
             }
         }
         if nest > 0 {
-            self.error_reporter.error(self.line, "Unexpected EOF");
+            self.error_reporter
+                .error(self.end_span(), Code::UnterminatedComment, "Unexpected EOF");
         }
         None
     }
 
     fn string(&mut self) -> Option<Token> {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            if self.advance() == '\n' {
+                self.new_line();
             }
-            self.advance();
         }
         if self.is_at_end() {
-            self.error_reporter.error(self.line, "Unterminated string");
+            self.error_reporter.error(
+                self.end_span(),
+                Code::UnterminatedString,
+                "Unterminated string",
+            );
             None
         } else {
             self.advance();
@@ -195,13 +220,46 @@ impl<'a> ScanTokens<'a> {
 
     fn start(&mut self) {
         self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column();
+        self.start_offset = self.offset;
+    }
+
+    fn column(&self) -> usize {
+        self.current - self.line_start + 1
+    }
+
+    fn lexeme_span(&self) -> Span {
+        Span {
+            line: self.start_line,
+            column: self.start_column,
+            offset: self.start_offset,
+            length: self.offset - self.start_offset,
+        }
+    }
+
+    /// An empty span at the current position, for errors found at the end of
+    /// the input.
+    fn end_span(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column(),
+            offset: self.offset,
+            length: 0,
+        }
+    }
+
+    /// Records that a newline was just consumed.
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
     }
 
     fn match_char(&mut self, expected: char) -> bool {
         if self.is_at_end() || self.source[self.current] != expected {
             false
         } else {
-            self.current += 1;
+            self.advance();
             true
         }
     }
@@ -209,6 +267,7 @@ impl<'a> ScanTokens<'a> {
     fn advance(&mut self) -> char {
         let ch = self.source[self.current];
         self.current += 1;
+        self.offset += ch.len_utf8();
         ch
     }
 
@@ -229,7 +288,13 @@ impl<'a> ScanTokens<'a> {
     }
 
     fn emit_token(&self, kind: TokenKind) -> Option<Token> {
-        Some(Token::new(kind, Rc::from(self.current_lexeme()), self.line))
+        Some(Token::new(
+            kind,
+            Rc::from(self.current_lexeme()),
+            self.start_line,
+            self.start_column,
+            self.start_offset,
+        ))
     }
 
     fn cond_emit(
@@ -288,7 +353,10 @@ mod tests {
     #[test]
     fn comment_works() {
         let tokens = self::scan_tokens("// this is a comment");
-        assert_eq!(tokens, vec![Token::new(TokenKind::Eof, "".into(), 1)])
+        assert_eq!(
+            tokens,
+            vec![Token::new(TokenKind::Eof, "".into(), 1, 21, 20)]
+        )
     }
 
     #[test]
@@ -297,13 +365,13 @@ mod tests {
         assert_eq!(
             tokens,
             vec![
-                Token::new(TokenKind::LeftParen, "(".into(), 1),
-                Token::new(TokenKind::LeftParen, "(".into(), 1),
-                Token::new(TokenKind::RightParen, ")".into(), 1),
-                Token::new(TokenKind::RightParen, ")".into(), 1),
-                Token::new(TokenKind::LeftBrace, "{".into(), 1),
-                Token::new(TokenKind::RightBrace, "}".into(), 1),
-                Token::new(TokenKind::Eof, "".into(), 1)
+                Token::new(TokenKind::LeftParen, "(".into(), 1, 1, 0),
+                Token::new(TokenKind::LeftParen, "(".into(), 1, 2, 1),
+                Token::new(TokenKind::RightParen, ")".into(), 1, 4, 3),
+                Token::new(TokenKind::RightParen, ")".into(), 1, 5, 4),
+                Token::new(TokenKind::LeftBrace, "{".into(), 1, 6, 5),
+                Token::new(TokenKind::RightBrace, "}".into(), 1, 7, 6),
+                Token::new(TokenKind::Eof, "".into(), 1, 8, 7)
             ]
         )
     }
@@ -314,17 +382,17 @@ mod tests {
         assert_eq!(
             tokens,
             vec![
-                Token::new(TokenKind::Bang, "!".into(), 1),
-                Token::new(TokenKind::Star, "*".into(), 1),
-                Token::new(TokenKind::Plus, "+".into(), 1),
-                Token::new(TokenKind::Minus, "-".into(), 1),
-                Token::new(TokenKind::Slash, "/".into(), 1),
-                Token::new(TokenKind::Equal, "=".into(), 1),
-                Token::new(TokenKind::Less, "<".into(), 1),
-                Token::new(TokenKind::Greater, ">".into(), 1),
-                Token::new(TokenKind::LessEqual, "<=".into(), 1),
-                Token::new(TokenKind::EqualEqual, "==".into(), 1),
-                Token::new(TokenKind::Eof, "".into(), 1)
+                Token::new(TokenKind::Bang, "!".into(), 1, 1, 0),
+                Token::new(TokenKind::Star, "*".into(), 1, 2, 1),
+                Token::new(TokenKind::Plus, "+".into(), 1, 3, 2),
+                Token::new(TokenKind::Minus, "-".into(), 1, 4, 3),
+                Token::new(TokenKind::Slash, "/".into(), 1, 5, 4),
+                Token::new(TokenKind::Equal, "=".into(), 1, 6, 5),
+                Token::new(TokenKind::Less, "<".into(), 1, 7, 6),
+                Token::new(TokenKind::Greater, ">".into(), 1, 8, 7),
+                Token::new(TokenKind::LessEqual, "<=".into(), 1, 10, 9),
+                Token::new(TokenKind::EqualEqual, "==".into(), 1, 13, 12),
+                Token::new(TokenKind::Eof, "".into(), 1, 15, 14)
             ]
         )
     }
@@ -335,8 +403,8 @@ mod tests {
         assert_eq!(
             tokens,
             vec![
-                Token::new(TokenKind::String("+ -".into()), "\"+ -\"".into(), 1),
-                Token::new(TokenKind::Eof, "".into(), 1)
+                Token::new(TokenKind::String("+ -".into()), "\"+ -\"".into(), 1, 1, 0),
+                Token::new(TokenKind::Eof, "".into(), 1, 6, 5)
             ]
         )
     }
@@ -347,10 +415,10 @@ mod tests {
         assert_eq!(
             tokens,
             vec![
-                Token::new(TokenKind::Number(4.14), "4.14".into(), 1),
-                Token::new(TokenKind::Plus, "+".into(), 1),
-                Token::new(TokenKind::Number(1.0), "1".into(), 1),
-                Token::new(TokenKind::Eof, "".into(), 1)
+                Token::new(TokenKind::Number(4.14), "4.14".into(), 1, 1, 0),
+                Token::new(TokenKind::Plus, "+".into(), 1, 6, 5),
+                Token::new(TokenKind::Number(1.0), "1".into(), 1, 8, 7),
+                Token::new(TokenKind::Eof, "".into(), 1, 9, 8)
             ]
         )
     }
@@ -361,12 +429,12 @@ mod tests {
         assert_eq!(
             tokens,
             vec![
-                Token::new(TokenKind::And, "and".into(), 1),
-                Token::new(TokenKind::Identifier, "andaluzja".into(), 1),
-                Token::new(TokenKind::And, "and".into(), 1),
-                Token::new(TokenKind::Identifier, "aluzja".into(), 1),
-                Token::new(TokenKind::Identifier, "And".into(), 1),
-                Token::new(TokenKind::Eof, "".into(), 1)
+                Token::new(TokenKind::And, "and".into(), 1, 1, 0),
+                Token::new(TokenKind::Identifier, "andaluzja".into(), 1, 5, 4),
+                Token::new(TokenKind::And, "and".into(), 1, 15, 14),
+                Token::new(TokenKind::Identifier, "aluzja".into(), 1, 19, 18),
+                Token::new(TokenKind::Identifier, "And".into(), 1, 26, 25),
+                Token::new(TokenKind::Eof, "".into(), 1, 29, 28)
             ]
         )
     }
@@ -377,9 +445,9 @@ mod tests {
         assert_eq!(
             tokens,
             vec![
-                Token::new(TokenKind::Identifier, "a".into(), 1),
-                Token::new(TokenKind::Identifier, "b".into(), 1),
-                Token::new(TokenKind::Eof, "".into(), 1)
+                Token::new(TokenKind::Identifier, "a".into(), 1, 1, 0),
+                Token::new(TokenKind::Identifier, "b".into(), 1, 13, 12),
+                Token::new(TokenKind::Eof, "".into(), 1, 14, 13)
             ]
         )
     }
@@ -390,13 +458,41 @@ mod tests {
         assert_eq!(
             tokens,
             vec![
-                Token::new(TokenKind::Identifier, "a".into(), 1),
-                Token::new(TokenKind::Identifier, "b".into(), 1),
-                Token::new(TokenKind::Eof, "".into(), 1)
+                Token::new(TokenKind::Identifier, "a".into(), 1, 1, 0),
+                Token::new(TokenKind::Identifier, "b".into(), 1, 19, 18),
+                Token::new(TokenKind::Eof, "".into(), 1, 20, 19)
             ]
         )
     }
 
+    #[test]
+    fn positions_work_across_lines() {
+        let tokens = self::scan_tokens("\"zażółć\" a\n  /* x\n */ b \"c\nd\"");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(
+                    TokenKind::String("zażółć".into()),
+                    "\"zażółć\"".into(),
+                    1,
+                    1,
+                    0
+                ),
+                Token::new(TokenKind::Identifier, "a".into(), 1, 10, 13),
+                Token::new(TokenKind::Identifier, "b".into(), 3, 5, 26),
+                Token::new(
+                    TokenKind::String("c\nd".into()),
+                    "\"c\nd\"".into(),
+                    3,
+                    7,
+                    28
+                ),
+                Token::new(TokenKind::Eof, "".into(), 4, 3, 33)
+            ]
+        );
+        assert_eq!(tokens[0].length, 12);
+    }
+
     fn scan_tokens(source: &str) -> Vec<Token> {
         let error_reporter = ErrorReporter::new();
         Scanner::new(&error_reporter).scan_tokens(source).collect()
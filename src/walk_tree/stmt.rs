@@ -17,9 +17,18 @@ pub enum Stmt {
         keyword: Token,
         expr: Option<Box<Expr>>,
     },
+    /// A `while` loop, or a desugared `for` loop whose increment runs after
+    /// the body even when the body continues.
     While {
         condition: Box<Expr>,
         body: Box<Stmt>,
+        increment: Option<Box<Expr>>,
+    },
+    Break {
+        keyword: Token,
+    },
+    Continue {
+        keyword: Token,
     },
     VarDeclaration {
         name: Token,
@@ -30,6 +39,21 @@ pub enum Stmt {
         superclass: Option<Box<Expr>>,
         methods: Box<[Function]>,
     },
+    Throw {
+        keyword: Token,
+        value: Box<Expr>,
+    },
+    Try {
+        body: Rc<[Box<Stmt>]>,
+        catch: Option<Catch>,
+        finally: Option<Rc<[Box<Stmt>]>>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Catch {
+    pub name: Token,
+    pub body: Rc<[Box<Stmt>]>,
 }
 
 impl Stmt {
@@ -5,16 +5,54 @@ pub struct Token {
     pub kind: TokenKind,
     lexeme: Rc<str>,
     pub line: usize,
+    /// One-based column of the first character, counted in characters.
+    pub column: usize,
+    /// Byte offset of the lexeme in the source.
+    pub offset: usize,
+    /// Length of the lexeme in bytes.
+    pub length: usize,
 }
 
 impl Token {
-    pub fn new(kind: TokenKind, lexeme: Rc<str>, line: usize) -> Self {
-        Self { kind, lexeme, line }
+    pub fn new(
+        kind: TokenKind,
+        lexeme: Rc<str>,
+        line: usize,
+        column: usize,
+        offset: usize,
+    ) -> Self {
+        let length = lexeme.len();
+        Self {
+            kind,
+            lexeme,
+            line,
+            column,
+            offset,
+            length,
+        }
     }
 
     pub fn lexeme(&self) -> &Rc<str> {
         &self.lexeme
     }
+
+    pub fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+            offset: self.offset,
+            length: self.length,
+        }
+    }
+}
+
+/// Where a piece of source code starts and how many bytes it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+    pub length: usize,
 }
 
 impl Display for Token {
@@ -57,9 +95,13 @@ pub enum TokenKind {
     Number(f64),
 
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
@@ -68,7 +110,9 @@ pub enum TokenKind {
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
     Eof,
@@ -102,9 +146,13 @@ impl Display for TokenKind {
             TokenKind::String(s) => write!(f, "\"{s}\""),
             TokenKind::Number(n) => write!(f, "\"{n}\""),
             TokenKind::And => write!(f, "and"),
+            TokenKind::Break => write!(f, "break"),
+            TokenKind::Catch => write!(f, "catch"),
             TokenKind::Class => write!(f, "class"),
+            TokenKind::Continue => write!(f, "continue"),
             TokenKind::Else => write!(f, "else"),
             TokenKind::False => write!(f, "false"),
+            TokenKind::Finally => write!(f, "finally"),
             TokenKind::Fun => write!(f, "fun"),
             TokenKind::For => write!(f, "for"),
             TokenKind::If => write!(f, "if"),
@@ -113,7 +161,9 @@ impl Display for TokenKind {
             TokenKind::Return => write!(f, "return"),
             TokenKind::Super => write!(f, "super"),
             TokenKind::This => write!(f, "this"),
+            TokenKind::Throw => write!(f, "throw"),
             TokenKind::True => write!(f, "true"),
+            TokenKind::Try => write!(f, "try"),
             TokenKind::Var => write!(f, "var"),
             TokenKind::While => write!(f, "while"),
             TokenKind::Eof => write!(f, "<EOF>"),
@@ -209,7 +209,7 @@ impl Cell {
     }
 
     pub fn is_class(&self) -> bool {
-        self.0.as_ref().map_or(false, |value| value.is_class())
+        self.0.as_ref().is_some_and(|value| value.is_class())
     }
 
     pub fn as_class(&self) -> Option<&Rc<Class>> {
@@ -0,0 +1,126 @@
+//! Runs every script in `tests/lox` through both interpreters and checks them
+//! against the annotations in the script and against each other.
+//!
+//! Supported annotations, each in a `//` comment at the end of a line:
+//!
+//! * `// expect: text` - the line `text` is printed to stdout.
+//! * `// expect runtime error: message` - the script fails at runtime on this
+//!   line with `message`.
+//! * `// Error at 'x': message` or `// [line N] Error at 'x': message` - the
+//!   script fails to compile with `[line N] Error at 'x': message`, where `N`
+//!   defaults to the annotated line.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+const EXPECT_ERROR: &str = "// Error";
+const EXPECT_ERROR_AT_LINE: &str = "// [line ";
+
+const DATA_ERR: i32 = 65;
+const SOFTWARE: i32 = 70;
+
+#[derive(Debug, Default, PartialEq)]
+struct Outcome {
+    stdout: String,
+    stderr: String,
+    exit_code: Option<i32>,
+}
+
+fn expected_outcome(source: &str) -> Outcome {
+    let mut expected = Outcome {
+        exit_code: Some(0),
+        ..Outcome::default()
+    };
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        if let Some((_, text)) = line.split_once(EXPECT) {
+            expected.stdout.push_str(text);
+            expected.stdout.push('\n');
+        } else if let Some((_, message)) = line.split_once(EXPECT_RUNTIME_ERROR) {
+            expected
+                .stderr
+                .push_str(&format!("{message}\n[line {line_number}]\n"));
+            expected.exit_code = Some(SOFTWARE);
+        } else if let Some((_, error)) = line.split_once(EXPECT_ERROR) {
+            expected
+                .stderr
+                .push_str(&format!("[line {line_number}] Error{error}\n"));
+            expected.exit_code = Some(DATA_ERR);
+        } else if let Some((_, error)) = line.split_once(EXPECT_ERROR_AT_LINE) {
+            expected.stderr.push_str(&format!("[line {error}\n"));
+            expected.exit_code = Some(DATA_ERR);
+        }
+    }
+    expected
+}
+
+fn run(interpreter: &str, script: &Path) -> Outcome {
+    let output = Command::new(interpreter)
+        .arg(script)
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run {interpreter}: {err}"));
+    Outcome {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        exit_code: output.status.code(),
+    }
+}
+
+fn describe(name: &str, outcome: &Outcome) -> String {
+    format!(
+        "  {name} exit code: {:?}\n  {name} stdout:\n{}  {name} stderr:\n{}",
+        outcome.exit_code, outcome.stdout, outcome.stderr
+    )
+}
+
+fn scripts(dir: &Path) -> Vec<PathBuf> {
+    let mut scripts = Vec::new();
+    for entry in fs::read_dir(dir).expect("Failed to read the test corpus.") {
+        let path = entry.expect("Failed to read a corpus entry.").path();
+        if path.is_dir() {
+            scripts.extend(self::scripts(&path));
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            scripts.push(path);
+        }
+    }
+    scripts.sort();
+    scripts
+}
+
+#[test]
+fn jlox_and_clox_agree_on_corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let scripts = scripts(&corpus);
+    assert!(!scripts.is_empty(), "No scripts in {}.", corpus.display());
+
+    let mut failures = Vec::new();
+    for script in scripts.iter() {
+        let source = fs::read_to_string(script).expect("Failed to read a script.");
+        let expected = expected_outcome(&source);
+        let jlox = run(env!("CARGO_BIN_EXE_jlox"), script);
+        let clox = run(env!("CARGO_BIN_EXE_clox"), script);
+
+        if jlox != expected || clox != expected {
+            failures.push(format!(
+                "{}:\n{}{}{}",
+                script.strip_prefix(&corpus).unwrap_or(script).display(),
+                describe("expected", &expected),
+                describe("jlox", &jlox),
+                describe("clox", &clox),
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} scripts failed:\n\n{}",
+        failures.len(),
+        scripts.len(),
+        failures.join("\n")
+    );
+}